@@ -0,0 +1,98 @@
+use std::{
+	collections::HashMap,
+	future::Future,
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicU64, Ordering},
+	},
+};
+
+use chrono::{DateTime, Local};
+use tokio::task::AbortHandle;
+
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+	pub id: u64,
+	pub uid: Option<u32>,
+	pub pid: Option<i32>,
+	pub connected_at: DateTime<Local>,
+}
+
+struct Client {
+	info: ClientInfo,
+	handle: AbortHandle,
+}
+
+/// Tracks the tasks serving unix socket clients so they can be listed and aborted.
+#[derive(Default)]
+pub struct ClientRegistry {
+	next_id: AtomicU64,
+	clients: Mutex<HashMap<u64, Client>>,
+}
+
+struct ClientGuard {
+	registry: Arc<ClientRegistry>,
+	id: u64,
+}
+
+impl Drop for ClientGuard {
+	fn drop(&mut self) {
+		if let Ok(mut clients) = self.registry.clients.lock() {
+			clients.remove(&self.id);
+		}
+	}
+}
+
+impl ClientRegistry {
+	pub fn spawn<F>(self: &Arc<Self>, uid: Option<u32>, pid: Option<i32>, task: F)
+	where
+		F: Future<Output = ()> + Send + 'static,
+	{
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let guard = ClientGuard {
+			registry: self.clone(),
+			id,
+		};
+
+		// hold the lock across the spawn so the guard can't remove the entry before it exists
+		let mut clients = self.clients.lock().expect("client registry poisoned");
+		let handle = tokio::spawn(async move {
+			let _guard = guard;
+			task.await;
+		});
+		clients.insert(
+			id,
+			Client {
+				info: ClientInfo {
+					id,
+					uid,
+					pid,
+					connected_at: Local::now(),
+				},
+				handle: handle.abort_handle(),
+			},
+		);
+	}
+
+	pub fn list(&self) -> Vec<ClientInfo> {
+		let clients = self.clients.lock().expect("client registry poisoned");
+		let mut list: Vec<ClientInfo> = clients.values().map(|x| x.info.clone()).collect();
+		list.sort_by_key(|x| x.id);
+		list
+	}
+
+	pub fn disconnect(&self, id: u64) -> bool {
+		let client = self
+			.clients
+			.lock()
+			.expect("client registry poisoned")
+			.remove(&id);
+		match client {
+			Some(client) => {
+				client.handle.abort();
+				true
+			}
+			None => false,
+		}
+	}
+}
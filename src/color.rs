@@ -0,0 +1,14 @@
+/// ANSI styles used when the client asks for colored output.
+pub const NAME: &str = "\x1b[36m";
+pub const DURATION: &str = "\x1b[32m";
+pub const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Wraps `text` in `style` if `enabled`, otherwise returns it unchanged.
+pub fn paint(text: &str, style: &str, enabled: bool) -> String {
+	if enabled {
+		format!("{style}{text}{RESET}")
+	} else {
+		text.to_string()
+	}
+}
@@ -0,0 +1,159 @@
+use std::{
+	collections::BTreeMap,
+	io::ErrorKind,
+	net::SocketAddr,
+	path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// What happens to the session when the user goes idle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnIdle {
+	/// End the session until there's input again
+	Pause,
+	/// Ignore idle and keep tracking
+	Keep,
+	/// Track `idle_activity` until there's input again
+	SwitchToIdleActivity,
+}
+
+/// What happens to the session when the system suspends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnSleep {
+	/// End the session until the system wakes up
+	Pause,
+	/// Keep tracking through the suspend
+	Keep,
+	/// Track `sleep_activity` until the system wakes up
+	SwitchToSleepActivity,
+}
+
+/// Daemon settings read from a TOML file. Daemon flags take precedence over values from the file,
+/// and values missing from both take the defaults below.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub database_path: String,
+	/// File holding the key to encrypt the database with, needs the `sqlcipher` feature
+	pub database_key_file: Option<PathBuf>,
+	pub idle_timeout: u32,
+	/// Wayland seat to watch for idle, all of them if unset
+	pub seat: Option<String>,
+	pub metrics_addr: Option<SocketAddr>,
+	pub notify: bool,
+	pub track_windows: bool,
+	pub idle_activity: Option<String>,
+	pub sleep_activity: Option<String>,
+	/// Defaults to `switch-to-idle-activity` if `idle_activity` is set and `pause` otherwise
+	pub on_idle: Option<OnIdle>,
+	/// Defaults to `switch-to-sleep-activity` if `sleep_activity` is set and `pause` otherwise
+	pub on_sleep: Option<OnSleep>,
+	pub request_timeout: u64,
+	/// Milliseconds to wait for a locked database before failing
+	pub busy_timeout: u64,
+	pub socket_path: Option<PathBuf>,
+	pub summary_cache_size: usize,
+	pub db_max_connections: u32,
+	/// Seconds between WAL checkpoints, 0 disables them
+	pub maintenance_interval: u64,
+	/// Also VACUUM the database at each maintenance run while nothing is being tracked
+	pub vacuum: bool,
+	/// Category name to glob patterns matched against activity names and UUIDs, for
+	/// `summary --group-by category`. Categories are tried in alphabetical order.
+	pub categories: BTreeMap<String, Vec<String>>,
+	/// Activity names or UUIDs that are never recorded
+	pub excluded_activities: Vec<String>,
+	/// Sessions shorter than this many seconds are deleted when they end
+	pub min_duration: u32,
+	/// Glob pattern to the seconds a day that matching activities may take before a notification
+	/// is shown.
+	pub goals: BTreeMap<String, u64>,
+	/// Activity name or UUID to the idle timeout in milliseconds used while it's tracked, instead
+	/// of `idle_timeout`
+	pub idle_timeouts: BTreeMap<String, u32>,
+	/// Activity tracked while the KDE activity manager isn't running
+	pub default_activity: String,
+	/// URL to POST tracking changes to
+	pub webhook_url: Option<String>,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			database_path: "~/.local/share/ktimetracker.db3".to_string(),
+			database_key_file: None,
+			idle_timeout: 30000,
+			seat: None,
+			metrics_addr: None,
+			notify: false,
+			track_windows: false,
+			idle_activity: None,
+			sleep_activity: None,
+			on_idle: None,
+			on_sleep: None,
+			request_timeout: 5000,
+			busy_timeout: 5000,
+			socket_path: None,
+			summary_cache_size: 32,
+			db_max_connections: 5,
+			maintenance_interval: 86400,
+			vacuum: false,
+			categories: BTreeMap::new(),
+			excluded_activities: Vec::new(),
+			min_duration: 0,
+			goals: BTreeMap::new(),
+			idle_timeouts: BTreeMap::new(),
+			default_activity: "unknown".to_string(),
+			webhook_url: None,
+		}
+	}
+}
+
+impl Config {
+	pub fn on_idle(&self) -> OnIdle {
+		self.on_idle.unwrap_or(if self.idle_activity.is_some() {
+			OnIdle::SwitchToIdleActivity
+		} else {
+			OnIdle::Pause
+		})
+	}
+
+	pub fn on_sleep(&self) -> OnSleep {
+		self.on_sleep.unwrap_or(if self.sleep_activity.is_some() {
+			OnSleep::SwitchToSleepActivity
+		} else {
+			OnSleep::Pause
+		})
+	}
+
+	/// `$XDG_CONFIG_HOME/ktimetracker/config.toml`, falling back to `~/.config` if unset.
+	pub fn default_path() -> Option<PathBuf> {
+		let config_home = std::env::var_os("XDG_CONFIG_HOME")
+			.filter(|x| !x.is_empty())
+			.map(PathBuf::from)
+			.or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))?;
+		Some(config_home.join("ktimetracker").join("config.toml"))
+	}
+
+	/// Loads the config file at `path`, or at [`Config::default_path`] if not given. A missing file
+	/// isn't an error and silently results in the default config.
+	pub fn load(path: Option<&Path>) -> Result<Self> {
+		let Some(path) = path.map(Path::to_path_buf).or_else(Self::default_path) else {
+			return Ok(Self::default());
+		};
+
+		match std::fs::read_to_string(&path) {
+			Ok(contents) => toml::from_str(&contents)
+				.with_context(|| format!("failed to parse config file {}", path.display())),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => {
+				Err(e).with_context(|| format!("failed to read config file {}", path.display()))
+			}
+		}
+	}
+}
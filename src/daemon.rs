@@ -1,52 +1,145 @@
 use tokio::{
-	io::{AsyncReadExt, AsyncWriteExt},
+	io::AsyncWrite,
 	net::UnixListener,
-	sync::mpsc,
+	sync::{mpsc, oneshot, watch},
 };
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
-use log::{debug, error, info, trace};
-use std::sync::Arc;
+use chrono::{
+	DateTime, Datelike, Duration, IsoWeek, Local, NaiveDate, NaiveDateTime, SecondsFormat,
+	TimeZone, Utc,
+};
+use futures::StreamExt;
+use log::{debug, error, info, trace, warn};
+use sd_notify::NotifyState;
+use serde::{Serialize, Serializer};
+use std::{
+	collections::{BTreeMap, HashSet},
+	os::unix::fs::MetadataExt,
+	path::Path,
+	pin::pin,
+	sync::Arc,
+};
 use tokio::{signal, task::JoinHandle};
+use zbus::zvariant::OwnedFd;
 
 use crate::{
-	Action, SummaryPeriod, db::Database, kactivities::KActivitiesConnection,
-	systemd::SystemdConnection, wayland::WaylandConnection,
+	Action, DurationFormat, ExportFormat, Format, GroupBy, RenderOptions, Request, SummaryPeriod,
+	VERSION,
+	clients::ClientRegistry,
+	color,
+	config::{Config, OnIdle, OnSleep},
+	db::{Database, DatabaseOptions, HEARTBEAT_INTERVAL, read_key_file},
+	dbus::DBusService,
+	ical,
+	idle::{self, IdleOptions},
+	kactivities::{ActivityInfo, ActivityResolver, KActivitiesConnection, NoActivityManager},
+	kwin::KWinConnection,
+	metrics::MetricsExporter,
+	notify::Notifier,
+	protocol::{self, ResponseWriter},
+	systemd::SystemdConnection,
+	table::Table,
+	util::{format_duration_as, glob_match, start_of_day},
+	webhook::{Webhook, WebhookEvent},
 };
-use serde_json;
 
-fn format_duration(duration: chrono::Duration) -> String {
-	let mut parts = Vec::new();
-	let hours = duration.num_hours();
-	if hours > 0 {
-		parts.push(format!("{}h", hours));
-	}
-	let minutes = duration.num_minutes() % 60;
-	if minutes > 0 {
-		parts.push(format!("{}m", minutes));
-	}
-	let seconds = duration.num_seconds() % 60;
-	if seconds > 0 {
-		parts.push(format!("{}s", seconds));
+fn format_share(duration: Duration, total: Duration) -> String {
+	if total.is_zero() {
+		return "0.0%".to_string();
 	}
-	if parts.is_empty() {
-		return "0s".to_string();
-	}
-	parts.join(" ")
+	let share = duration.num_milliseconds() as f64 / total.num_milliseconds() as f64;
+	format!("{:.1}%", share * 100.0)
+}
+
+/// Subsystems that feed events into the daemon.
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+	Wayland,
+	ScreenSaver,
+	Systemd,
+	KActivities,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct SourceHealth {
+	wayland: bool,
+	screensaver: bool,
+	systemd: bool,
+	kactivities: bool,
+}
+
+pub struct DaemonStatus {
+	sources: SourceHealth,
+	tracking: TrackingState,
+	database_path: String,
+}
+
+#[derive(Serialize)]
+struct StatusEntry {
+	sources: SourceHealth,
+	uuid: Option<String>,
+	name: Option<String>,
+	idle: bool,
+	paused: bool,
+	database_path: String,
 }
 
 pub enum DaemonEvent {
-	KdeActivityChanged { activity: String },
-	IdleStatusChanged { idle: bool },
-	SleepingNow,
+	KdeActivityChanged {
+		activity: String,
+	},
+	IdleStatusChanged {
+		idle: bool,
+	},
+	/// `inhibitor` delays suspend until it's dropped, which must happen once the database is
+	/// up to date.
+	SleepingNow {
+		inhibitor: Option<OwnedFd>,
+	},
 	WakingNow,
+	ScreenLocked,
+	ScreenUnlocked,
+	Pause,
+	Resume,
+	Start {
+		activity: String,
+	},
+	WindowChanged {
+		app_id: Option<String>,
+	},
+	SourceStatus {
+		source: Source,
+		connected: bool,
+	},
+	Status {
+		reply: oneshot::Sender<DaemonStatus>,
+	},
+	/// SIGHUP was received.
+	ReloadConfig,
 }
 
 pub struct Daemon {
 	event_tx: mpsc::UnboundedSender<DaemonEvent>,
 	event_rx: mpsc::UnboundedReceiver<DaemonEvent>,
-	idle_duration: u32,
+	config: Config,
+	/// reads the config again, with the daemon's flags applied on top
+	load_config: Box<dyn Fn() -> Result<Config> + Send + Sync>,
+	/// category map served to clients, replaced when the config is reloaded
+	categories_tx: watch::Sender<Arc<BTreeMap<String, Vec<String>>>>,
+	paused: bool,
+	idle: bool,
+	/// nothing is tracked while the screen is locked, whatever else happens in the meantime
+	locked: bool,
+	manual_activity: Option<String>,
+	app_id: Option<String>,
+	sources: SourceHealth,
+	state_tx: watch::Sender<TrackingState>,
+	/// goals already exceeded on `goals_day`, so each one only notifies once a day
+	goals_exceeded: HashSet<String>,
+	goals_day: Option<NaiveDate>,
+	/// idle timeout for the tracked activity, watched by the idle detection task
+	idle_timeout_tx: watch::Sender<u32>,
 }
 
 macro_rules! swrite {
@@ -59,269 +152,2658 @@ macro_rules! swrite {
 }
 
 fn parse_datetime(s: String) -> anyhow::Result<DateTime<Local>> {
+	parse_datetime_at(&s, Local::now())
+}
+
+/// Parses an absolute date, a named day like `today`, or an offset like `7d`, `24h` or `30m` that
+/// counts back from `now`. Dates without an offset are interpreted in the time zone of `now`.
+fn parse_datetime_at<Tz: TimeZone>(s: &str, now: DateTime<Tz>) -> anyhow::Result<DateTime<Tz>> {
+	let tz = now.timezone();
+
+	let s = s.trim();
+	match s.to_lowercase().as_str() {
+		"now" => return Ok(now),
+		"today" => return Ok(start_of_day(&tz, now.date_naive())),
+		"yesterday" => return Ok(start_of_day(&tz, now.date_naive() - chrono::Days::new(1))),
+		"this week" => {
+			let weekday = now.weekday().num_days_from_monday();
+			return Ok(start_of_day(
+				&tz,
+				now.date_naive() - chrono::Days::new(weekday as u64),
+			));
+		}
+		_ => {}
+	}
+
+	let offset = if let Some(days) = s.strip_suffix('d') {
+		days.parse().ok().and_then(Duration::try_days)
+	} else if let Some(hours) = s.strip_suffix('h') {
+		hours.parse().ok().and_then(Duration::try_hours)
+	} else if let Some(minutes) = s.strip_suffix('m') {
+		minutes.parse().ok().and_then(Duration::try_minutes)
+	} else {
+		None
+	};
+	if let Some(offset) = offset
+		&& let Some(dt) = now.clone().checked_sub_signed(offset)
+	{
+		return Ok(dt);
+	}
+
 	// Try different formats
-	if let Ok(dt) = DateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S") {
-		return Ok(dt.with_timezone(&Local));
+	if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+		&& let Some(dt) = tz.from_local_datetime(&dt).earliest()
+	{
+		return Ok(dt);
 	}
-	if let Ok(dt) = NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
-		return Ok(Local
-			.from_local_datetime(&dt.and_hms_opt(0, 0, 0).unwrap())
-			.unwrap());
+	if let Ok(dt) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+		return Ok(start_of_day(&tz, dt));
 	}
-	if let Ok(dt) = NaiveDate::parse_from_str(&s, "%d/%m/%Y") {
-		return Ok(Local
-			.from_local_datetime(&dt.and_hms_opt(0, 0, 0).unwrap())
-			.unwrap());
+	if let Ok(dt) = NaiveDate::parse_from_str(s, "%d/%m/%Y") {
+		return Ok(start_of_day(&tz, dt));
 	}
 	Err(anyhow::anyhow!("Invalid date format"))
 }
 
-async fn handle_unix_client(
-	stream: &mut tokio::net::UnixStream,
+/// Start and end of the time a summary covers, `None` where it's unbounded.
+type SummaryWindow = (Option<DateTime<Local>>, Option<DateTime<Local>>);
+
+/// Resolves the window a summary covers, with day boundaries taken from the time zone of `now`.
+fn summary_window<Tz: TimeZone>(
+	period: Option<SummaryPeriod>,
+	start_time: Option<String>,
+	end_time: Option<String>,
+	now: DateTime<Tz>,
+) -> Result<SummaryWindow> {
+	let tz = now.timezone();
+	let start = match period {
+		Some(SummaryPeriod::Today) => start_of_day(&tz, now.date_naive()),
+		Some(SummaryPeriod::ThisWeek) => {
+			let weekday = now.weekday().num_days_from_monday();
+			start_of_day(&tz, now.date_naive() - chrono::Days::new(weekday as u64))
+		}
+		Some(SummaryPeriod::ThisMonth) => start_of_day(
+			&tz,
+			NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap(),
+		),
+		None => {
+			let start = start_time
+				.map(|s| parse_datetime_at(&s, now.clone()))
+				.transpose()
+				.context("Failed to parse start")?;
+			let end = end_time
+				.map(|s| parse_datetime_at(&s, now.clone()))
+				.transpose()
+				.context("Failed to parse end")?;
+			return Ok((
+				start.map(|x| x.with_timezone(&Local)),
+				end.map(|x| x.with_timezone(&Local)),
+			));
+		}
+	};
+	Ok((Some(start.with_timezone(&Local)), None))
+}
+
+/// The window a summary is compared against: the previous day, week or month for periods, or the
+/// same length of time right before the start otherwise.
+fn previous_window<Tz: TimeZone>(
+	period: Option<SummaryPeriod>,
+	start: Option<DateTime<Local>>,
+	end: Option<DateTime<Local>>,
+	now: DateTime<Tz>,
+) -> Result<(DateTime<Local>, DateTime<Local>)> {
+	let tz = now.timezone();
+	let Some(start) = start else {
+		anyhow::bail!("--compare needs a start time to compare against");
+	};
+	let start_date = start.with_timezone(&tz).date_naive();
+	let previous_start = match period {
+		Some(SummaryPeriod::Today) => start_of_day(&tz, start_date - chrono::Days::new(1)),
+		Some(SummaryPeriod::ThisWeek) => start_of_day(&tz, start_date - chrono::Days::new(7)),
+		Some(SummaryPeriod::ThisMonth) => start_of_day(&tz, start_date - chrono::Months::new(1)),
+		None => {
+			let end = end.unwrap_or_else(|| now.with_timezone(&Local));
+			return Ok((start - (end - start), start));
+		}
+	};
+	Ok((previous_start.with_timezone(&Local), start))
+}
+
+/// Width of the longest bar in the heatmap.
+const HEATMAP_WIDTH: i64 = 40;
+
+/// Renders one bar per hour of the day, scaled so the busiest hour fills `HEATMAP_WIDTH`.
+fn render_heatmap(hours: &[Duration; 24], color: bool, durations: DurationFormat) -> String {
+	let max = hours.iter().max().map_or(1, |x| x.num_seconds().max(1));
+	let mut out = String::new();
+	for (hour, duration) in hours.iter().enumerate() {
+		let width = (duration.num_seconds() * HEATMAP_WIDTH / max) as usize;
+		let bar = format!(
+			"{:<width$}",
+			"█".repeat(width),
+			width = HEATMAP_WIDTH as usize
+		);
+		out.push_str(&format!(
+			"{hour:02}:00 | {} {}\n",
+			color::paint(&bar, color::DURATION, color),
+			format_duration_as(*duration, durations)
+		));
+	}
+	out
+}
+
+/// Labels a week like `2024-W23`, using the ISO week-numbering year rather than the calendar one.
+fn iso_week_label(week: IsoWeek) -> String {
+	format!("{}-W{:02}", week.year(), week.week())
+}
+
+/// Formats a change in duration, always with a sign.
+fn format_delta(delta: Duration, durations: DurationFormat) -> String {
+	if delta < Duration::zero() {
+		format_duration_as(delta, durations)
+	} else {
+		format!("+{}", format_duration_as(delta, durations))
+	}
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+	DateTime::from_timestamp(timestamp, 0).map_or_else(
+		|| timestamp.to_string(),
+		|x| x.to_rfc3339_opts(SecondsFormat::Secs, true),
+	)
+}
+
+fn csv_escape(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_i64(duration.num_seconds())
+}
+
+#[derive(Serialize)]
+struct SummaryEntry {
+	activity: String,
+	uuid: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	app_id: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	day: Option<NaiveDate>,
+	/// ISO week label like `2024-W23`
+	#[serde(skip_serializing_if = "Option::is_none")]
+	week: Option<String>,
+	#[serde(rename = "seconds", serialize_with = "serialize_seconds")]
+	duration: Duration,
+}
+
+#[derive(Serialize)]
+struct StatsEntry {
+	activity: String,
+	uuid: String,
+	#[serde(rename = "seconds", serialize_with = "serialize_seconds")]
+	duration: Duration,
+	sessions: u64,
+	#[serde(rename = "average_seconds", serialize_with = "serialize_seconds")]
+	average: Duration,
+	#[serde(rename = "longest_seconds", serialize_with = "serialize_seconds")]
+	longest: Duration,
+}
+
+#[derive(Serialize)]
+struct ComparisonEntry {
+	activity: String,
+	#[serde(rename = "seconds", serialize_with = "serialize_seconds")]
+	duration: Duration,
+	#[serde(rename = "previous_seconds", serialize_with = "serialize_seconds")]
+	previous: Duration,
+	#[serde(rename = "delta_seconds", serialize_with = "serialize_seconds")]
+	delta: Duration,
+}
+
+/// Joins two summaries on the activity, counting activities missing from one as zero there.
+/// Activities of the current summary come first, in its order.
+fn compare_summaries(
+	current: Vec<SummaryEntry>,
+	previous: Vec<SummaryEntry>,
+) -> Vec<ComparisonEntry> {
+	let mut previous: Vec<_> = previous
+		.into_iter()
+		.map(|x| (x.activity, x.duration))
+		.collect();
+	let mut comparison = Vec::new();
+	for entry in current {
+		let previous_duration = match previous.iter().position(|(x, _)| *x == entry.activity) {
+			Some(i) => previous.remove(i).1,
+			None => Duration::zero(),
+		};
+		comparison.push(ComparisonEntry {
+			activity: entry.activity,
+			duration: entry.duration,
+			previous: previous_duration,
+			delta: entry.duration - previous_duration,
+		});
+	}
+	for (activity, previous_duration) in previous {
+		comparison.push(ComparisonEntry {
+			activity,
+			duration: Duration::zero(),
+			previous: previous_duration,
+			delta: -previous_duration,
+		});
+	}
+	comparison
+}
+
+#[derive(Serialize)]
+struct EventEntry {
+	id: i64,
+	activity: String,
+	uuid: String,
+	start_time: String,
+	end_time: Option<String>,
+	#[serde(rename = "seconds", serialize_with = "serialize_seconds")]
+	duration: Duration,
+	app_id: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TimelineEntry {
+	/// `None` for time nothing was tracked
+	activity: Option<String>,
+	uuid: Option<String>,
+	start_time: String,
+	end_time: String,
+	#[serde(rename = "seconds", serialize_with = "serialize_seconds")]
+	duration: Duration,
+}
+
+#[derive(Serialize)]
+struct CurrentEntry {
+	uuid: String,
+	name: String,
+	description: String,
+	icon: String,
+	start: Option<String>,
+	elapsed_seconds: Option<i64>,
+	idle: bool,
+}
+
+#[derive(Serialize)]
+struct WatchEntry {
+	uuid: Option<String>,
+	name: Option<String>,
+	idle: bool,
+	paused: bool,
+}
+
+/// What the daemon is currently doing, published to `watch` clients after every event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingState {
+	pub activity: Option<String>,
+	pub idle: bool,
+	pub paused: bool,
+}
+
+#[derive(Clone)]
+struct ClientContext {
 	db: Arc<Database>,
+	activities: Arc<dyn ActivityResolver>,
+	clients: Arc<ClientRegistry>,
+	event_tx: mpsc::UnboundedSender<DaemonEvent>,
+	state_rx: watch::Receiver<TrackingState>,
+	request_timeout: std::time::Duration,
+	categories: watch::Receiver<Arc<BTreeMap<String, Vec<String>>>>,
+}
+
+/// Abstract unix socket used when no socket path is configured.
+pub const SOCKET_NAME: &str = "\0dev.r58playz.ktimetracker";
+
+/// Binds a socket file, removing a stale one left behind by a daemon that didn't exit cleanly.
+fn bind_socket_file(path: &Path) -> Result<UnixListener> {
+	if std::os::unix::net::UnixStream::connect(path).is_ok() {
+		anyhow::bail!(
+			"ktimetracker daemon is already running on {}",
+			path.display()
+		);
+	}
+	match std::fs::remove_file(path) {
+		Ok(()) => debug!("removed stale socket {}", path.display()),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+		Err(e) => {
+			return Err(e)
+				.with_context(|| format!("failed to remove stale socket {}", path.display()));
+		}
+	}
+	UnixListener::bind(path).with_context(|| format!("failed to bind to {}", path.display()))
+}
+
+/// Effective uid of the daemon, which owns its entry in procfs.
+fn current_uid() -> Result<u32> {
+	Ok(std::fs::metadata("/proc/self")
+		.context("failed to look up the daemon's uid")?
+		.uid())
+}
+
+/// Keeps systemd's status line for the unit in sync with what's being tracked.
+async fn report_status(
+	mut state_rx: watch::Receiver<TrackingState>,
 	kactivities_conn: KActivitiesConnection,
+) {
+	loop {
+		let state = state_rx.borrow_and_update().clone();
+		let status = match (&state.activity, state.paused, state.idle) {
+			(Some(uuid), _, _) => {
+				let name = match kactivities_conn.query_activity_info(uuid.clone()).await {
+					Ok(info) if !info.name.is_empty() => info.name,
+					_ => uuid.clone(),
+				};
+				format!("Tracking {name}")
+			}
+			(None, true, _) => "Paused".to_string(),
+			(None, false, true) => "Idle".to_string(),
+			(None, false, false) => "Not tracking".to_string(),
+		};
+		let _ = sd_notify::notify(false, &[NotifyState::Status(&status)]);
+
+		if state_rx.changed().await.is_err() {
+			break;
+		}
+	}
+}
+
+/// [`Database::get_summary`], optionally with the idle time recorded within sessions taken out.
+async fn activity_summary(
+	db: &Database,
+	start: Option<DateTime<Local>>,
+	end: Option<DateTime<Local>>,
+	exclude_idle: bool,
+) -> Result<Vec<(String, Duration)>> {
+	let mut summary = db.get_summary(start, end).await?;
+	if exclude_idle {
+		let idle = db.get_idle_summary(start, end).await?;
+		for (name, duration) in &mut summary {
+			if let Some((_, idle)) = idle.iter().find(|(x, _)| x == name) {
+				*duration -= *idle;
+			}
+		}
+		summary.retain(|(_, duration)| *duration > Duration::zero());
+		summary.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	}
+	Ok(summary)
+}
+
+/// Resolves activity names through KActivities, then applies `filter` and groups by category if
+/// asked to.
+async fn resolve_summary(
+	ctx: &ClientContext,
+	summary: Vec<(Option<NaiveDate>, String, Option<String>, Duration)>,
+	filter: Option<&str>,
+	group_by: GroupBy,
+) -> Result<Vec<SummaryEntry>> {
+	let mut resolved_summary = Vec::new();
+	for (day, activity_uuid, app_id, duration) in summary {
+		let activity_info = ctx
+			.activities
+			.query_activity_info(activity_uuid.clone())
+			.await?;
+		let activity_name = if activity_info.name.is_empty() {
+			activity_uuid.clone()
+		} else {
+			activity_info.name
+		};
+		let (day, week) = if group_by == GroupBy::Week {
+			(None, day.map(|x| iso_week_label(x.iso_week())))
+		} else {
+			(day, None)
+		};
+		resolved_summary.push(SummaryEntry {
+			activity: activity_name,
+			uuid: activity_uuid,
+			app_id,
+			day,
+			week,
+			duration,
+		});
+	}
+	trace!("resolved summary");
+
+	if let Some(filter) = filter {
+		let filter = filter.to_lowercase();
+		resolved_summary.retain(|x| {
+			x.activity.to_lowercase().contains(&filter) || x.uuid.to_lowercase().contains(&filter)
+		});
+	}
+
+	if group_by == GroupBy::Category {
+		let categories = ctx.categories.borrow().clone();
+		resolved_summary = categorize(resolved_summary, &categories);
+	}
+	Ok(resolved_summary)
+}
+
+/// Merges summary entries into one per category, the first whose patterns match the activity's
+/// name or UUID, sorted by duration like the summary itself.
+fn categorize(
+	entries: Vec<SummaryEntry>,
+	categories: &BTreeMap<String, Vec<String>>,
+) -> Vec<SummaryEntry> {
+	let mut durations: BTreeMap<&str, Duration> = BTreeMap::new();
+	for entry in &entries {
+		let category = categories
+			.iter()
+			.find(|(_, patterns)| {
+				patterns.iter().any(|pattern| {
+					glob_match(pattern, &entry.activity) || glob_match(pattern, &entry.uuid)
+				})
+			})
+			.map_or("Uncategorized", |(category, _)| category.as_str());
+		*durations.entry(category).or_insert_with(Duration::zero) += entry.duration;
+	}
+
+	let mut categorized: Vec<_> = durations
+		.into_iter()
+		.map(|(category, duration)| SummaryEntry {
+			activity: category.to_string(),
+			uuid: String::new(),
+			app_id: None,
+			day: None,
+			week: None,
+			duration,
+		})
+		.collect();
+	categorized.sort_by(|a, b| {
+		b.duration
+			.cmp(&a.duration)
+			.then_with(|| a.activity.cmp(&b.activity))
+	});
+	categorized
+}
+
+/// Sessions shown by `events` when no limit is given.
+const DEFAULT_EVENTS_LIMIT: i64 = 50;
+
+/// How often goals are checked while an activity is ongoing, on top of every activity switch.
+const GOAL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Requests are a single serialized `Request`, so anything larger is a broken or hostile client.
+const MAX_REQUEST_SIZE: u32 = 64 * 1024;
+
+/// Sends `event` with the activity tracked from now on. The webhook is best effort, so a failed
+/// lookup skips it rather than stopping the daemon.
+async fn send_webhook(webhook: Option<&Webhook>, db: &Database, event: WebhookEvent) {
+	let Some(webhook) = webhook else {
+		return;
+	};
+	match db.get_current_activity().await {
+		Ok(activity) => webhook.send(event, activity),
+		Err(e) => warn!("failed to look up the activity for the {event:?} webhook: {e:#}"),
+	}
+}
+
+async fn handle_unix_client(stream: &mut tokio::net::UnixStream, ctx: ClientContext) -> Result<()> {
+	let buf = tokio::time::timeout(
+		ctx.request_timeout,
+		protocol::read_request(&mut *stream, MAX_REQUEST_SIZE),
+	)
+	.await
+	.context("timed out waiting for request")?
+	.context("failed to read request")?;
+	let request: Request = match serde_json::from_slice(&buf) {
+		Ok(request) => request,
+		Err(e) => {
+			// most likely an old daemon left running after an upgrade, so point at that instead
+			let version = serde_json::from_slice::<serde_json::Value>(&buf)
+				.ok()
+				.and_then(|x| Some(x.get("version")?.as_str()?.to_string()))
+				.unwrap_or_default();
+			let e = anyhow::Error::new(e);
+			if version != VERSION {
+				let version = if version.is_empty() {
+					"unknown"
+				} else {
+					&version
+				};
+				return Err(e.context(format!(
+					"Failed to deserialize request from client version {version}, daemon is version {VERSION}; restart the daemon"
+				)));
+			}
+			return Err(e.context("Failed to deserialize request"));
+		}
+	};
+	let mut stream = ResponseWriter::new(stream);
+
+	match (request.action, request.actions) {
+		(Some(action), actions) if actions.is_empty() => {
+			handle_action(&mut stream, &ctx, action, &request.render).await
+		}
+		(None, actions) if !actions.is_empty() => {
+			// a failing action doesn't stop the rest, its error is its response
+			for action in actions {
+				if let Err(e) = handle_action(&mut stream, &ctx, action, &request.render).await {
+					stream.error(&e.to_string()).await?;
+				}
+				stream.end().await?;
+			}
+			Ok(())
+		}
+		_ => anyhow::bail!("request needs either an action or a list of actions"),
+	}
+}
+
+/// Renders a read-only action straight from the database at `database_path` to `out`, for when
+/// the daemon isn't running or to look at a backup. Activity names come from KDE if it's reachable.
+pub async fn run_offline<W: AsyncWrite + Unpin>(
+	out: W,
+	database_path: &str,
+	key: Option<String>,
+	categories: BTreeMap<String, Vec<String>>,
+	action: Action,
+	render: RenderOptions,
+) -> Result<()> {
+	if !matches!(
+		action,
+		Action::Summary { .. }
+			| Action::Events { .. }
+			| Action::Timeline { .. }
+			| Action::Export { .. }
+			| Action::Heatmap { .. }
+	) {
+		anyhow::bail!(
+			"only summary, events, timeline, export and heatmap can read the database directly, everything else needs the daemon"
+		);
+	}
+
+	let db = Database::new(
+		database_path,
+		DatabaseOptions {
+			summary_cache_size: 0,
+			max_connections: 1,
+			busy_timeout: std::time::Duration::from_millis(Config::default().busy_timeout),
+			min_duration: 0,
+			key,
+			read_only: true,
+		},
+	)
+	.await?;
+	// nothing reads these, but the activity manager connection stops once they're gone
+	let (event_tx, _event_rx) = mpsc::unbounded_channel();
+	let activities: Arc<dyn ActivityResolver> =
+		match KActivitiesConnection::new(event_tx.clone(), String::new()).await {
+			Ok(conn) => Arc::new(conn),
+			Err(_) => Arc::new(NoActivityManager),
+		};
+	let ctx = ClientContext {
+		db: Arc::new(db),
+		activities,
+		clients: Arc::new(ClientRegistry::default()),
+		event_tx,
+		state_rx: watch::channel(TrackingState::default()).1,
+		request_timeout: std::time::Duration::ZERO,
+		categories: watch::channel(Arc::new(categories)).1,
+	};
+
+	let result = handle_action(&mut ResponseWriter::unframed(out), &ctx, action, &render).await;
+	ctx.db.close().await;
+	result
+}
+
+async fn handle_action<W: AsyncWrite + Unpin>(
+	stream: &mut ResponseWriter<W>,
+	ctx: &ClientContext,
+	action: Action,
+	render: &RenderOptions,
 ) -> Result<()> {
-	let mut buf = Vec::new();
-	stream.read_to_end(&mut buf).await?;
-	let action: Action = serde_json::from_slice(&buf).context("Failed to deserialize action")?;
+	let color = render.color;
+	let durations = render.duration_format;
+	let width = render.width;
 
 	match action {
 		Action::Summary {
 			period,
 			start_time,
 			end_time,
+			format,
+			top,
+			other,
+			filter,
+			group_by,
+			timezone,
+			no_header,
+			compare,
+			stats,
+			exclude_idle,
+			round_now,
+			coverage,
 		} => {
 			trace!("handling summary command");
 
-			let (start, end) = match period {
-				Some(SummaryPeriod::Today) => {
-					let now = Local::now();
-					let start = now.date_naive().and_hms_opt(0, 0, 0).unwrap();
-					(Some(Local.from_local_datetime(&start).unwrap()), None)
-				}
-				Some(SummaryPeriod::ThisWeek) => {
-					let now = Local::now();
-					let weekday = now.weekday().num_days_from_monday();
-					let start = (now.date_naive() - chrono::Days::new(weekday as u64))
-						.and_hms_opt(0, 0, 0)
-						.unwrap();
-					(Some(Local.from_local_datetime(&start).unwrap()), None)
+			let tz = timezone
+				.map(|timezone| {
+					timezone
+						.parse::<chrono_tz::Tz>()
+						.map_err(|e| anyhow::anyhow!("unknown time zone {timezone}: {e}"))
+				})
+				.transpose()?;
+			let (start, end, previous) = match tz {
+				Some(tz) => {
+					let now = Utc::now().with_timezone(&tz);
+					let (start, end) = summary_window(period, start_time, end_time, now)?;
+					let previous = compare
+						.then(|| previous_window(period, start, end, now))
+						.transpose()?;
+					(start, end, previous)
 				}
-				Some(SummaryPeriod::ThisMonth) => {
+				None => {
 					let now = Local::now();
-					let start = NaiveDate::from_ymd_opt(now.year(), now.month(), 1)
-						.unwrap()
-						.and_hms_opt(0, 0, 0)
-						.unwrap();
-					(Some(Local.from_local_datetime(&start).unwrap()), None)
+					let (start, end) = summary_window(period, start_time, end_time, now)?;
+					let previous = compare
+						.then(|| previous_window(period, start, end, now))
+						.transpose()?;
+					(start, end, previous)
 				}
-				None => {
-					let start = start_time
-						.map(|s| parse_datetime(s))
-						.transpose()
-						.context("Failed to parse start_time")?;
-					let end = end_time
-						.map(|s| parse_datetime(s))
-						.transpose()
-						.context("Failed to parse end_time")?;
-					(start, end)
+			};
+			// an open window ends now, which moves every second while an activity is tracked
+			let end = match (end, round_now) {
+				(None, Some(0)) => anyhow::bail!("--round-now needs at least 1 second"),
+				(None, Some(unit)) => {
+					let now = Utc::now().timestamp();
+					let rounded = now - now.rem_euclid(unit.into());
+					Some(
+						DateTime::from_timestamp(rounded, 0)
+							.context("rounded time out of range")?
+							.with_timezone(&Local),
+					)
 				}
+				(end, _) => end,
 			};
+			if compare && matches!(group_by, GroupBy::App | GroupBy::Day | GroupBy::Week) {
+				anyhow::bail!("--compare only supports grouping by activity or category");
+			}
+			if exclude_idle && matches!(group_by, GroupBy::App | GroupBy::Day | GroupBy::Week) {
+				anyhow::bail!("--exclude-idle only supports grouping by activity or category");
+			}
+			if coverage && format != Format::Table {
+				anyhow::bail!("--coverage only applies to table output");
+			}
+			if stats && group_by != GroupBy::Activity {
+				anyhow::bail!("--stats only supports grouping by activity");
+			}
+
+			if stats {
+				let mut stats = ctx.db.get_session_stats(start, end).await?;
+				let summary = stats
+					.iter()
+					.map(|x| (None, x.name.clone(), None, x.total))
+					.collect();
+				let resolved_summary =
+					resolve_summary(ctx, summary, filter.as_deref(), group_by).await?;
+				let total = resolved_summary
+					.iter()
+					.fold(Duration::zero(), |acc, x| acc + x.duration);
+
+				let mut entries = Vec::new();
+				for entry in resolved_summary {
+					let i = stats
+						.iter()
+						.position(|x| x.name == entry.uuid)
+						.with_context(|| format!("no session stats for {}", entry.uuid))?;
+					entries.push((entry, stats.swap_remove(i)));
+				}
+
+				match format {
+					Format::Table => {
+						let mut table = Table::new(&[
+							"Activity", "Duration", "Sessions", "Average", "Longest", "Share",
+						]);
+						if color {
+							table.style(0, color::NAME);
+							table.style(1, color::DURATION);
+						}
+						if let Some(width) = width {
+							table.fit(0, width);
+						}
+						let mut sessions = 0;
+						for (entry, stats) in entries {
+							sessions += stats.sessions;
+							table.push(vec![
+								entry.activity,
+								format_duration_as(entry.duration, durations),
+								stats.sessions.to_string(),
+								format_duration_as(stats.average(), durations),
+								format_duration_as(stats.longest, durations),
+								format_share(entry.duration, total),
+							]);
+						}
+						table.set_footer(vec![
+							"Total".to_string(),
+							format_duration_as(total, durations),
+							sessions.to_string(),
+							String::new(),
+							String::new(),
+							format_share(total, total),
+						]);
+						swrite!(stream, "{}", table.render())?;
+					}
+					Format::Json => {
+						let entries: Vec<_> = entries
+							.into_iter()
+							.map(|(entry, stats)| StatsEntry {
+								average: stats.average(),
+								activity: entry.activity,
+								uuid: entry.uuid,
+								duration: entry.duration,
+								sessions: stats.sessions,
+								longest: stats.longest,
+							})
+							.collect();
+						swrite!(stream, "{}\n", serde_json::to_string(&entries)?)?;
+					}
+				}
+				return Ok(());
+			}
 
-			let mut summary: Vec<(String, Duration)> =
-				db.get_summary(start, end).await?.into_iter().collect();
-			summary.sort_by_key(|x| x.1);
-			summary.reverse();
+			let summary = match group_by {
+				GroupBy::Activity | GroupBy::Category => {
+					activity_summary(&ctx.db, start, end, exclude_idle)
+						.await?
+						.into_iter()
+						.map(|(name, duration)| (None, name, None, duration))
+						.collect()
+				}
+				GroupBy::App => ctx
+					.db
+					.get_app_summary(start, end)
+					.await?
+					.into_iter()
+					.map(|(name, app_id, duration)| (None, name, app_id, duration))
+					.collect(),
+				GroupBy::Day => {
+					let daily = match tz {
+						Some(tz) => ctx.db.get_daily_summary(start, end, tz).await?,
+						None => ctx.db.get_daily_summary(start, end, Local).await?,
+					};
+					daily
+						.into_iter()
+						.map(|(day, name, duration)| (Some(day), name, None, duration))
+						.collect::<Vec<_>>()
+				}
+				// weeks are keyed by their Monday until they're resolved
+				GroupBy::Week => {
+					let weekly = match tz {
+						Some(tz) => ctx.db.get_weekly_summary(start, end, tz).await?,
+						None => ctx.db.get_weekly_summary(start, end, Local).await?,
+					};
+					weekly
+						.into_iter()
+						.map(|(monday, name, duration)| (Some(monday), name, None, duration))
+						.collect::<Vec<_>>()
+				}
+			};
 			trace!("got summary");
 
-			let mut max_activity_len = "Activity".len();
-			let mut max_duration_len = "Duration".len();
+			let mut resolved_summary =
+				resolve_summary(ctx, summary, filter.as_deref(), group_by).await?;
 
-			let mut resolved_summary = Vec::new();
-			for (activity_uuid, duration) in summary {
-				let activity_info = kactivities_conn
-					.query_activity_info(activity_uuid.clone())
-					.await?;
-				let activity_name = if activity_info.name.is_empty() {
-					activity_uuid
-				} else {
-					activity_info.name
-				};
-				resolved_summary.push((activity_name, format_duration(duration)));
+			if let Some((previous_start, previous_end)) = previous {
+				let previous = activity_summary(
+					&ctx.db,
+					Some(previous_start),
+					Some(previous_end),
+					exclude_idle,
+				)
+				.await?
+				.into_iter()
+				.map(|(name, duration)| (None, name, None, duration))
+				.collect();
+				let previous = resolve_summary(ctx, previous, filter.as_deref(), group_by).await?;
+				let comparison = compare_summaries(resolved_summary, previous);
+
+				match format {
+					Format::Table => {
+						let mut table = Table::new(&[
+							if group_by == GroupBy::Category {
+								"Category"
+							} else {
+								"Activity"
+							},
+							"Duration",
+							"Previous",
+							"Change",
+						]);
+						if color {
+							table.style(0, color::NAME);
+							table.style(1, color::DURATION);
+						}
+						if let Some(width) = width {
+							table.fit(0, width);
+						}
+						let mut totals = (Duration::zero(), Duration::zero());
+						for entry in comparison {
+							totals.0 += entry.duration;
+							totals.1 += entry.previous;
+							table.push(vec![
+								entry.activity,
+								format_duration_as(entry.duration, durations),
+								format_duration_as(entry.previous, durations),
+								format_delta(entry.delta, durations),
+							]);
+						}
+						table.set_footer(vec![
+							"Total".to_string(),
+							format_duration_as(totals.0, durations),
+							format_duration_as(totals.1, durations),
+							format_delta(totals.0 - totals.1, durations),
+						]);
+						swrite!(stream, "{}", table.render())?;
+					}
+					Format::Json => {
+						swrite!(stream, "{}\n", serde_json::to_string(&comparison)?)?;
+					}
+				}
+				return Ok(());
 			}
-			trace!("resolved summary");
 
-			for (activity, duration) in &resolved_summary {
-				max_activity_len = max_activity_len.max(activity.len());
-				max_duration_len = max_duration_len.max(duration.len());
+			let total = resolved_summary
+				.iter()
+				.fold(Duration::zero(), |acc, x| acc + x.duration);
+
+			// the summary is already sorted by duration, so the busiest activities come first
+			if let Some(top) = top
+				&& resolved_summary.len() > top
+			{
+				let rest = resolved_summary.split_off(top);
+				if other {
+					resolved_summary.push(SummaryEntry {
+						activity: "Other".to_string(),
+						uuid: String::new(),
+						app_id: None,
+						day: None,
+						week: None,
+						duration: rest
+							.iter()
+							.fold(Duration::zero(), |acc, x| acc + x.duration),
+					});
+				}
 			}
 
-			let separator = format!("{:->max_activity_len$}-+{:->max_duration_len$}\n", "", "");
+			match format {
+				Format::Table if resolved_summary.is_empty() && !no_header => {
+					swrite!(stream, "No time tracked in this period\n")?;
+				}
+				Format::Table => {
+					let by_day = matches!(group_by, GroupBy::Day | GroupBy::Week);
+					let by_app = group_by == GroupBy::App;
+					let mut headers = Vec::new();
+					if group_by == GroupBy::Day {
+						headers.push("Day");
+					} else if group_by == GroupBy::Week {
+						headers.push("Week");
+					}
+					headers.push(if group_by == GroupBy::Category {
+						"Category"
+					} else {
+						"Activity"
+					});
+					if by_app {
+						headers.push("App");
+					}
+					headers.extend(["Duration", "Share"]);
 
-			swrite!(stream, separator)?;
-			swrite!(
-				stream,
-				"{:<max_activity_len$} | {:<max_duration_len$}\n",
-				"Activity",
-				"Duration"
-			)?;
-			swrite!(stream, separator)?;
+					let mut table = Table::new(&headers);
+					let activity_column = if by_day { 1 } else { 0 };
+					if color {
+						table.style(activity_column, color::NAME);
+						table.style(headers.len() - 2, color::DURATION);
+					}
+					if let Some(width) = width {
+						table.fit(activity_column, width);
+					}
+					let mut rows = Vec::new();
+					for entry in resolved_summary {
+						let mut row = Vec::new();
+						if by_day {
+							row.push(
+								entry
+									.day
+									.map(|x| x.to_string())
+									.or(entry.week)
+									.unwrap_or_default(),
+							);
+						}
+						row.push(entry.activity);
+						if by_app {
+							row.push(entry.app_id.unwrap_or_else(|| "N/A".to_string()));
+						}
+						row.push(format_duration_as(entry.duration, durations));
+						if no_header {
+							rows.push(row);
+						} else {
+							row.push(format_share(entry.duration, total));
+							table.push(row);
+						}
+					}
 
-			for (activity, duration) in resolved_summary {
-				swrite!(
-					stream,
-					"{:<max_activity_len$} | {:<max_duration_len$}\n",
-					activity,
-					duration
-				)?;
+					if no_header {
+						// just the data, tab separated for cut and awk
+						for row in rows {
+							swrite!(stream, "{}\n", row.join("\t"))?;
+						}
+						return Ok(());
+					}
+
+					let mut footer = vec!["Total".to_string()];
+					footer.resize(headers.len() - 2, String::new());
+					footer.push(format_duration_as(total, durations));
+					footer.push(format_share(total, total));
+					table.set_footer(footer);
+					swrite!(stream, "{}", table.render())?;
+
+					let downtime = ctx.db.get_downtime(start, end).await?;
+					if downtime > Duration::zero() {
+						swrite!(
+							stream,
+							"Not tracked while the daemon wasn't running: {}\n",
+							format_duration_as(downtime, durations)
+						)?;
+					}
+
+					if coverage {
+						let window_start = match start {
+							Some(start) => Some(start.with_timezone(&Utc)),
+							None => ctx.db.first_start_time().await?,
+						};
+						// nothing can be tracked in the future, so the window ends now at the latest
+						let now = Utc::now();
+						let window_end = end.map_or(now, |x| x.with_timezone(&Utc).min(now));
+						let window = window_start
+							.map_or(Duration::zero(), |x| window_end - x)
+							.max(Duration::zero());
+						swrite!(
+							stream,
+							"Tracked {} of the {} window\n",
+							format_share(total.min(window), window),
+							format_duration_as(window, durations)
+						)?;
+					}
+				}
+				Format::Json => {
+					swrite!(stream, "{}\n", serde_json::to_string(&resolved_summary)?)?;
+				}
 			}
-			swrite!(stream, separator)?;
 		}
-		Action::Current => {
+		Action::Current { format } => {
 			trace!("handling current command");
-			let current_uuid = db.get_current_activity().await?;
-			let elapsed_time = db.get_current_activity_elapsed_time().await?;
+			let Some(current_uuid) = ctx.db.get_current_activity().await? else {
+				match format {
+					Format::Table => swrite!(stream, "No activity is currently being tracked\n")?,
+					Format::Json => swrite!(stream, "null\n")?,
+				}
+				return Ok(());
+			};
+			let start_time = ctx.db.get_current_activity_start().await?;
+			let elapsed_time = start_time.map(|x| Utc::now() - x);
 			trace!("got activity from db");
 
-			let activity_info = kactivities_conn
+			let activity_info = ctx
+				.activities
 				.query_activity_info(current_uuid.clone())
 				.await?;
-			let (name, description) = if activity_info.name.is_empty() {
-				(current_uuid, String::new())
+			let (name, description, icon) = if activity_info.name.is_empty() {
+				(current_uuid.clone(), String::new(), String::new())
 			} else {
-				(activity_info.name, activity_info.description)
+				(
+					activity_info.name,
+					activity_info.description,
+					activity_info.icon,
+				)
 			};
 			trace!("got kde activity info from db");
 
-			swrite!(
-				stream,
-				"Current Activity: {}\nDescription: {}\nElapsed Time: {}\n",
-				name,
-				description,
-				elapsed_time.map_or("N/A".to_string(), format_duration)
-			)?;
+			match format {
+				Format::Table => {
+					swrite!(
+						stream,
+						"Current Activity: {}\nDescription: {}\nIcon: {}\nStarted: {}\nElapsed Time: {}\n",
+						color::paint(&name, color::HIGHLIGHT, color),
+						description,
+						icon,
+						start_time.map_or("N/A".to_string(), |x| x
+							.with_timezone(&Local)
+							.format("%Y-%m-%d %H:%M:%S")
+							.to_string()),
+						color::paint(
+							&elapsed_time.map_or("N/A".to_string(), |x| {
+								format_duration_as(x, durations)
+							}),
+							color::DURATION,
+							color
+						)
+					)?;
+				}
+				Format::Json => {
+					let current = CurrentEntry {
+						uuid: current_uuid,
+						name,
+						description,
+						icon,
+						start: start_time.map(|x| format_timestamp(x.timestamp())),
+						elapsed_seconds: elapsed_time.map(|x| x.num_seconds()),
+						idle: ctx.state_rx.borrow().idle,
+					};
+					swrite!(stream, "{}\n", serde_json::to_string(&current)?)?;
+				}
+			}
 		}
-	}
-	Ok(())
-}
+		Action::Watch { format } => {
+			trace!("handling watch command");
 
-impl Daemon {
-	pub fn new(idle_duration: u32) -> Self {
-		let (event_tx, event_rx) = mpsc::unbounded_channel();
-		Self {
-			event_tx,
-			event_rx,
-			idle_duration,
+			let mut state_rx = ctx.state_rx.clone();
+			loop {
+				let state = state_rx.borrow_and_update().clone();
+				let name = match &state.activity {
+					Some(uuid) => {
+						let activity_info =
+							ctx.activities.query_activity_info(uuid.clone()).await?;
+						if activity_info.name.is_empty() {
+							Some(uuid.clone())
+						} else {
+							Some(activity_info.name)
+						}
+					}
+					None => None,
+				};
+
+				let res = match format {
+					Format::Table => {
+						let status = match (&name, state.paused, state.idle) {
+							(Some(name), _, _) => format!("Tracking {name}"),
+							(None, true, _) => "Paused".to_string(),
+							(None, false, true) => "Idle".to_string(),
+							(None, false, false) => "Not tracking".to_string(),
+						};
+						swrite!(stream, "[{}] {status}\n", Local::now().format("%H:%M:%S"))
+					}
+					Format::Json => {
+						let entry = WatchEntry {
+							uuid: state.activity,
+							name,
+							idle: state.idle,
+							paused: state.paused,
+						};
+						swrite!(stream, "{}\n", serde_json::to_string(&entry)?)
+					}
+				};
+				if res.is_err() {
+					trace!("watch client disconnected");
+					break;
+				}
+
+				if state_rx.changed().await.is_err() {
+					break;
+				}
+			}
 		}
-	}
+		Action::Status { format } => {
+			trace!("handling status command");
 
-	pub async fn run(mut self, database_path: &str) -> Result<()> {
-		info!("starting daemon");
+			let (reply, rx) = oneshot::channel();
+			ctx.event_tx
+				.send(DaemonEvent::Status { reply })
+				.context("failed to send status request to daemon")?;
+			let status = rx.await.context("failed to get status from daemon")?;
 
-		let db = Arc::new(Database::new(database_path).await?);
-		let kactivities_conn = KActivitiesConnection::new(self.event_tx.clone()).await?;
+			let name = match &status.tracking.activity {
+				Some(uuid) => {
+					let activity_info = ctx
+						.activities
+						.query_activity_info(uuid.clone())
+						.await
+						.unwrap_or_else(|_| ActivityInfo::default());
+					if activity_info.name.is_empty() {
+						Some(uuid.clone())
+					} else {
+						Some(activity_info.name)
+					}
+				}
+				None => None,
+			};
 
-		let mut signal_handle = tokio::spawn({
-			let db_clone = db.clone();
-			async move {
-				let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
-				let mut sigint = signal::unix::signal(signal::unix::SignalKind::interrupt())?;
-				tokio::select! {
-					_ = sigterm.recv() => {},
-					_ = sigint.recv() => {},
-				};
-				trace!("got signal, saving state");
-				db_clone.end_current_activity().await
+			match format {
+				Format::Table => {
+					let connected = |x: bool| if x { "connected" } else { "disconnected" };
+					swrite!(
+						stream,
+						"Wayland: {}\nScreenSaver: {}\nSystemd: {}\nKDE Activities: {}\nCurrent Activity: {}\nIdle: {}\nPaused: {}\nDatabase: {}\n",
+						connected(status.sources.wayland),
+						connected(status.sources.screensaver),
+						connected(status.sources.systemd),
+						connected(status.sources.kactivities),
+						name.as_deref().unwrap_or("N/A"),
+						if status.tracking.idle { "yes" } else { "no" },
+						if status.tracking.paused { "yes" } else { "no" },
+						status.database_path
+					)?;
+				}
+				Format::Json => {
+					let entry = StatusEntry {
+						sources: status.sources,
+						uuid: status.tracking.activity,
+						name,
+						idle: status.tracking.idle,
+						paused: status.tracking.paused,
+						database_path: status.database_path,
+					};
+					swrite!(stream, "{}\n", serde_json::to_string(&entry)?)?;
+				}
 			}
-		});
+		}
+		Action::List => {
+			trace!("handling list command");
 
-		let initial_activity = kactivities_conn.query_current_activity().await?;
-		db.switch_activity(&initial_activity).await?;
-		trace!("kde activity changed to {initial_activity}");
+			let mut table = Table::new(&["UUID", "Name", "Description"]);
+			for activity_uuid in ctx.activities.query_activities().await? {
+				let activity_info = ctx
+					.activities
+					.query_activity_info(activity_uuid.clone())
+					.await?;
+				table.push(vec![
+					activity_uuid,
+					activity_info.name,
+					activity_info.description,
+				]);
+			}
 
-		let mut wayland_handle = tokio::spawn(WaylandConnection::daemon(
-			self.event_tx.clone(),
-			self.idle_duration,
-		));
+			swrite!(stream, "{}", table.render())?;
+		}
+		Action::Heatmap {
+			activity,
+			start,
+			end,
+		} => {
+			trace!("handling heatmap command");
 
-		let mut systemd_handle = tokio::spawn(
-			SystemdConnection::new(self.event_tx.clone())
-				.await?
-				.daemon(),
-		);
+			let start = start
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse start")?;
+			let end = end
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse end")?;
 
-		let listener = UnixListener::bind("\0dev.r58playz.ktimetracker")?;
-		let mut unix_socket_handle: JoinHandle<Result<()>> = tokio::spawn({
-			let db = db.clone();
-			let kactivities_conn = kactivities_conn.clone();
-			async move {
+			// sessions are stored by UUID, so look up KDE activities given by name
+			let name = match activity {
+				Some(activity) => {
+					let mut name = activity.clone();
+					for uuid in ctx.activities.query_activities().await? {
+						let activity_info =
+							ctx.activities.query_activity_info(uuid.clone()).await?;
+						if activity_info.name == activity {
+							name = uuid;
+							break;
+						}
+					}
+					Some(name)
+				}
+				None => None,
+			};
+
+			let hours = ctx
+				.db
+				.get_hourly_summary(start, end, name.as_deref(), Local)
+				.await?;
+			swrite!(stream, "{}", render_heatmap(&hours, color, durations))?;
+		}
+		Action::Events {
+			limit,
+			offset,
+			format,
+		} => {
+			trace!("handling events command");
+
+			let limit = limit.unwrap_or(DEFAULT_EVENTS_LIMIT);
+			let offset = offset.unwrap_or(0);
+			if limit < 0 || offset < 0 {
+				anyhow::bail!("limit and offset can't be negative");
+			}
+
+			let now = Utc::now().timestamp();
+			let mut entries = Vec::new();
+			for row in ctx.db.get_events(limit, offset).await? {
+				let activity_info = ctx.activities.query_activity_info(row.name.clone()).await?;
+				let activity = if activity_info.name.is_empty() {
+					row.name.clone()
+				} else {
+					activity_info.name
+				};
+				entries.push(EventEntry {
+					id: row.id,
+					activity,
+					uuid: row.name,
+					start_time: format_timestamp(row.start_time),
+					end_time: row.end_time.map(format_timestamp),
+					duration: Duration::seconds(row.end_time.unwrap_or(now) - row.start_time),
+					app_id: row.app_id,
+					note: row.note,
+				});
+			}
+
+			match format {
+				Format::Table => {
+					// only show the column once there's something in it
+					let with_notes = entries.iter().any(|x| x.note.is_some());
+					let mut headers = vec!["ID", "Activity", "Start", "End", "Duration", "App"];
+					if with_notes {
+						headers.push("Note");
+					}
+					let mut table = Table::new(&headers);
+					if color {
+						table.style(1, color::NAME);
+						table.style(4, color::DURATION);
+					}
+					if let Some(width) = width {
+						table.fit(1, width);
+					}
+					for entry in entries {
+						let mut row = vec![
+							entry.id.to_string(),
+							entry.activity,
+							entry.start_time,
+							entry.end_time.unwrap_or_else(|| "ongoing".to_string()),
+							format_duration_as(entry.duration, durations),
+							entry.app_id.unwrap_or_else(|| "N/A".to_string()),
+						];
+						if with_notes {
+							row.push(entry.note.unwrap_or_default());
+						}
+						table.push(row);
+					}
+					swrite!(stream, "{}", table.render())?;
+				}
+				Format::Json => {
+					swrite!(stream, "{}\n", serde_json::to_string(&entries)?)?;
+				}
+			}
+		}
+		Action::Timeline { date, format } => {
+			trace!("handling timeline command");
+
+			let date = parse_datetime(date)
+				.context("Failed to parse date")?
+				.date_naive();
+			let day_start = start_of_day(&Local, date);
+			// today's timeline ends now rather than with a gap until midnight
+			let day_end = start_of_day(&Local, date + chrono::Days::new(1)).min(Local::now());
+			if day_start >= day_end {
+				anyhow::bail!("{date} hasn't started yet");
+			}
+
+			// gaps are filled in between sessions, so the entries cover the whole day
+			let mut timeline = Vec::new();
+			let mut cursor = day_start.timestamp();
+			for (name, start, end) in ctx.db.get_timeline(day_start, day_end).await? {
+				if start > cursor {
+					timeline.push((None, cursor, start));
+				}
+				cursor = cursor.max(end);
+				timeline.push((Some(name), start, end));
+			}
+			if cursor < day_end.timestamp() {
+				timeline.push((None, cursor, day_end.timestamp()));
+			}
+
+			let mut entries = Vec::new();
+			for (uuid, start, end) in timeline {
+				let activity = match &uuid {
+					Some(uuid) => {
+						let activity_info =
+							ctx.activities.query_activity_info(uuid.clone()).await?;
+						if activity_info.name.is_empty() {
+							Some(uuid.clone())
+						} else {
+							Some(activity_info.name)
+						}
+					}
+					None => None,
+				};
+				let entry = TimelineEntry {
+					activity,
+					uuid,
+					start_time: format_timestamp(start),
+					end_time: format_timestamp(end),
+					duration: Duration::seconds(end - start),
+				};
+				entries.push((entry, start, end));
+			}
+
+			match format {
+				Format::Table => {
+					let mut table = Table::new(&["Start", "End", "Duration", "Activity"]);
+					if color {
+						table.style(2, color::DURATION);
+						table.style(3, color::NAME);
+					}
+					if let Some(width) = width {
+						table.fit(3, width);
+					}
+					let time = |timestamp: i64| {
+						DateTime::from_timestamp(timestamp, 0).map_or_else(
+							|| timestamp.to_string(),
+							|x| x.with_timezone(&Local).format("%H:%M:%S").to_string(),
+						)
+					};
+					for (entry, start, end) in entries {
+						table.push(vec![
+							time(start),
+							time(end),
+							format_duration_as(entry.duration, durations),
+							entry.activity.unwrap_or_else(|| "Untracked".to_string()),
+						]);
+					}
+					swrite!(stream, "{}", table.render())?;
+				}
+				Format::Json => {
+					let entries: Vec<_> = entries.into_iter().map(|(entry, _, _)| entry).collect();
+					swrite!(stream, "{}\n", serde_json::to_string(&entries)?)?;
+				}
+			}
+		}
+		Action::Export { start, end, format } => {
+			trace!("handling export command");
+
+			let start = start
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse start")?;
+			let end = end
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse end")?;
+
+			// rows are written as they're fetched, so multi-year exports never sit in memory and a
+			// client reading slowly holds back the query instead of the daemon buffering for it
+			let mut rows = pin!(ctx.db.export_rows(start, end));
+			match format {
+				ExportFormat::Csv => {
+					swrite!(stream, "id,name,start_time,end_time,app_id\n")?;
+					while let Some(row) = rows.next().await {
+						let row = row?;
+						swrite!(
+							stream,
+							"{},{},{},{},{}\n",
+							row.id,
+							csv_escape(&row.name),
+							format_timestamp(row.start_time),
+							row.end_time.map(format_timestamp).unwrap_or_default(),
+							row.app_id.as_deref().map(csv_escape).unwrap_or_default()
+						)?;
+					}
+				}
+				ExportFormat::Ics => {
+					let now = Utc::now();
+					swrite!(stream, "{}", ical::header())?;
+					while let Some(row) = rows.next().await {
+						let row = row?;
+						let activity_info =
+							ctx.activities.query_activity_info(row.name.clone()).await?;
+						let name = if activity_info.name.is_empty() {
+							row.name
+						} else {
+							activity_info.name
+						};
+						let end_time = row.end_time.unwrap_or(now.timestamp());
+						swrite!(
+							stream,
+							"{}",
+							ical::event(row.id, &name, row.start_time, end_time, now)
+						)?;
+					}
+					swrite!(stream, "{}", ical::footer())?;
+				}
+			}
+		}
+		Action::Pause => {
+			trace!("handling pause command");
+
+			ctx.event_tx
+				.send(DaemonEvent::Pause)
+				.context("failed to send pause to daemon")?;
+			swrite!(stream, "Tracking paused\n")?;
+		}
+		Action::Resume => {
+			trace!("handling resume command");
+
+			ctx.event_tx
+				.send(DaemonEvent::Resume)
+				.context("failed to send resume to daemon")?;
+			swrite!(stream, "Tracking resumed\n")?;
+		}
+		Action::Start { name } => {
+			trace!("handling start command");
+
+			ctx.event_tx
+				.send(DaemonEvent::Start {
+					activity: name.clone(),
+				})
+				.context("failed to send start to daemon")?;
+			swrite!(stream, "Started tracking {name}\n")?;
+		}
+		Action::Rename { from, to } => {
+			trace!("handling rename command");
+
+			let renamed = ctx.db.rename_activity(&from, &to).await?;
+			swrite!(stream, "Renamed {renamed} sessions from {from} to {to}\n")?;
+		}
+		Action::Note { id, text } => {
+			trace!("handling note command");
+
+			if !ctx.db.set_note(id, &text).await? {
+				match id {
+					Some(id) => anyhow::bail!("no session with id {id}"),
+					None => anyhow::bail!("nothing is being tracked"),
+				}
+			}
+			let target = id.map_or("current session".to_string(), |x| format!("session {x}"));
+			if text.is_empty() {
+				swrite!(stream, "Removed note from {target}\n")?;
+			} else {
+				swrite!(stream, "Noted {target}\n")?;
+			}
+		}
+		Action::Edit { id, start, end } => {
+			trace!("handling edit command");
+
+			if start.is_none() && end.is_none() {
+				anyhow::bail!("nothing to edit, pass --start and/or --end");
+			}
+			let start = start
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse start")?;
+			let end = end
+				.map(parse_datetime)
+				.transpose()
+				.context("Failed to parse end")?;
+
+			if !ctx.db.update_event_times(id, start, end).await? {
+				anyhow::bail!("no session with id {id}");
+			}
+			swrite!(stream, "Updated session {id}\n")?;
+		}
+		Action::Delete { id } => {
+			trace!("handling delete command");
+
+			if !ctx.db.delete_event(id).await? {
+				anyhow::bail!("no session with id {id}");
+			}
+			swrite!(stream, "Deleted 1 session\n")?;
+		}
+		Action::Purge { before } => {
+			trace!("handling purge command");
+
+			let cutoff = parse_datetime(before).context("Failed to parse before")?;
+			let purged = ctx.db.purge_before(cutoff).await?;
+			swrite!(stream, "Purged {purged} sessions\n")?;
+		}
+		Action::Repair => {
+			trace!("handling repair command");
+
+			let repaired = ctx.db.repair().await?;
+			swrite!(stream, "Repaired {repaired} sessions\n")?;
+		}
+		Action::Backup { path, force } => {
+			trace!("handling backup command");
+
+			if tokio::fs::try_exists(&path).await? {
+				if !force {
+					anyhow::bail!("{path} already exists, pass --force to overwrite it");
+				}
+				tokio::fs::remove_file(&path)
+					.await
+					.with_context(|| format!("failed to remove {path}"))?;
+			}
+			ctx.db.backup(&path).await?;
+			let size = tokio::fs::metadata(&path).await?.len();
+			swrite!(stream, "Backed up database to {path} ({size} bytes)\n")?;
+		}
+		Action::Connections => {
+			trace!("handling connections command");
+
+			let mut table = Table::new(&["ID", "UID", "PID", "Connected"]);
+			for client in ctx.clients.list() {
+				table.push(vec![
+					client.id.to_string(),
+					client.uid.map_or("N/A".to_string(), |x| x.to_string()),
+					client.pid.map_or("N/A".to_string(), |x| x.to_string()),
+					client.connected_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+				]);
+			}
+
+			swrite!(stream, "{}", table.render())?;
+		}
+		Action::Disconnect { id } => {
+			trace!("handling disconnect command");
+
+			if !ctx.clients.disconnect(id) {
+				return Err(anyhow::anyhow!("no client with id {id}"));
+			}
+			swrite!(stream, "Disconnected client {id}\n")?;
+		}
+		Action::Version => {
+			trace!("handling version command");
+
+			swrite!(stream, "daemon {VERSION}\n")?;
+		}
+	}
+	Ok(())
+}
+
+impl Daemon {
+	pub fn new(config: Config, load_config: Box<dyn Fn() -> Result<Config> + Send + Sync>) -> Self {
+		let (event_tx, event_rx) = mpsc::unbounded_channel();
+		let idle_timeout_tx = watch::Sender::new(config.idle_timeout);
+		let categories_tx = watch::Sender::new(Arc::new(config.categories.clone()));
+		Self {
+			event_tx,
+			event_rx,
+			config,
+			load_config,
+			categories_tx,
+			paused: false,
+			idle: false,
+			locked: false,
+			manual_activity: None,
+			app_id: None,
+			sources: SourceHealth::default(),
+			state_tx: watch::Sender::new(TrackingState::default()),
+			goals_exceeded: HashSet::new(),
+			goals_day: None,
+			idle_timeout_tx,
+		}
+	}
+
+	async fn publish_state(&self, db: &Database) -> Result<()> {
+		let activity = db.get_current_activity().await?;
+		let state = TrackingState {
+			activity,
+			idle: self.idle,
+			paused: self.paused,
+		};
+		self.state_tx.send_if_modified(|x| {
+			if *x == state {
+				return false;
+			}
+			*x = state;
+			true
+		});
+		Ok(())
+	}
+
+	async fn tracked_activity(&self, kactivities_conn: &KActivitiesConnection) -> Result<String> {
+		match &self.manual_activity {
+			Some(activity) => Ok(activity.clone()),
+			None => kactivities_conn.query_current_activity().await,
+		}
+	}
+
+	async fn is_excluded(&self, activities: &dyn ActivityResolver, activity: &str) -> Result<bool> {
+		let excluded = &self.config.excluded_activities;
+		if excluded.is_empty() {
+			return Ok(false);
+		}
+		if excluded.iter().any(|x| x == activity) {
+			return Ok(true);
+		}
+		let info = activities.query_activity_info(activity.to_string()).await?;
+		Ok(!info.name.is_empty() && excluded.contains(&info.name))
+	}
+
+	/// Starts a session of `activity`, or only ends the current session if it's excluded so
+	/// switching back out of it starts tracking again.
+	async fn switch_to(
+		&self,
+		db: &Database,
+		activities: &dyn ActivityResolver,
+		activity: &str,
+	) -> Result<()> {
+		self.apply_idle_timeout(activities, activity).await?;
+		if self.is_excluded(activities, activity).await? {
+			trace!("not tracking excluded activity {activity}");
+			db.end_current_activity().await
+		} else {
+			db.switch_activity(activity, self.app_id.as_deref()).await
+		}
+	}
+
+	/// Follows a change of the KDE activity unless tracking is held by a pause, a manual activity
+	/// or the locked screen. Returns whether the daemon switched to it.
+	async fn kde_activity_changed(
+		&self,
+		db: &Database,
+		activities: &dyn ActivityResolver,
+		activity: &str,
+	) -> Result<bool> {
+		if self.paused {
+			trace!("ignoring activity change to {activity}: paused");
+		} else if self.manual_activity.is_some() {
+			trace!("ignoring activity change to {activity}: manual activity set");
+		} else if self.locked {
+			trace!("ignoring activity change to {activity}: screen locked");
+		} else {
+			trace!("activity changed to {activity}");
+			self.switch_to(db, activities, activity).await?;
+			return Ok(true);
+		}
+		Ok(false)
+	}
+
+	async fn screen_locked(&mut self, db: &Database) -> Result<()> {
+		trace!("ending current activity: screen locked");
+		self.locked = true;
+		db.end_current_activity().await
+	}
+
+	/// Switches idle detection to the timeout configured for `activity`, or the global one.
+	async fn apply_idle_timeout(
+		&self,
+		activities: &dyn ActivityResolver,
+		activity: &str,
+	) -> Result<()> {
+		let idle_timeout = Self::idle_timeout_for(&self.config, activities, activity).await?;
+		self.set_idle_timeout(activity, idle_timeout);
+		Ok(())
+	}
+
+	/// Idle timeout `config` sets for `activity`, looked up by uuid and then by name.
+	async fn idle_timeout_for(
+		config: &Config,
+		activities: &dyn ActivityResolver,
+		activity: &str,
+	) -> Result<u32> {
+		let overrides = &config.idle_timeouts;
+		let mut idle_timeout = overrides.get(activity).copied();
+		if idle_timeout.is_none() && !overrides.is_empty() {
+			let info = activities.query_activity_info(activity.to_string()).await?;
+			idle_timeout = overrides.get(&info.name).copied();
+		}
+		Ok(idle_timeout.unwrap_or(config.idle_timeout))
+	}
+
+	fn set_idle_timeout(&self, activity: &str, idle_timeout: u32) {
+		self.idle_timeout_tx.send_if_modified(|x| {
+			if *x == idle_timeout {
+				return false;
+			}
+			debug!("idle timeout is now {idle_timeout}ms for {activity}");
+			*x = idle_timeout;
+			true
+		});
+	}
+
+	/// Applies a config read again on SIGHUP. Settings that are only used at startup keep their
+	/// old value with a warning, and the current session is left running either way.
+	async fn reload_config(
+		&mut self,
+		kactivities_conn: &KActivitiesConnection,
+		has_notifier: bool,
+	) -> Result<()> {
+		let mut config = (self.load_config)()?;
+
+		let mut restart_required = Vec::new();
+		macro_rules! keep {
+			($($field:ident),*) => {$(
+				if config.$field != self.config.$field {
+					restart_required.push(stringify!($field));
+					config.$field = self.config.$field.clone();
+				}
+			)*};
+		}
+		keep!(
+			database_path,
+			database_key_file,
+			seat,
+			metrics_addr,
+			notify,
+			track_windows,
+			request_timeout,
+			busy_timeout,
+			socket_path,
+			summary_cache_size,
+			db_max_connections,
+			maintenance_interval,
+			min_duration,
+			default_activity,
+			webhook_url
+		);
+		// goals are only checked if there's a notifier to report them through
+		if !has_notifier && !config.goals.is_empty() {
+			restart_required.push("goals");
+		}
+
+		// everything that can fail is done before the new config replaces the old one
+		let activity = self.tracked_activity(kactivities_conn).await?;
+		let idle_timeout = Self::idle_timeout_for(&config, kactivities_conn, &activity).await?;
+
+		if !restart_required.is_empty() {
+			warn!(
+				"restart the daemon to apply changes to {}",
+				restart_required.join(", ")
+			);
+		}
+		self.config = config;
+		self.categories_tx
+			.send_replace(Arc::new(self.config.categories.clone()));
+		self.set_idle_timeout(&activity, idle_timeout);
+		Ok(())
+	}
+
+	/// Checkpoints the WAL, and vacuums too if enabled and nothing is being tracked, since it would
+	/// stall writes to the open session.
+	async fn maintain(&self, db: &Database) -> Result<()> {
+		db.checkpoint().await?;
+		info!("checkpointed database");
+		if self.config.vacuum {
+			if db.get_current_activity().await?.is_some() {
+				info!("skipping vacuum, an activity is being tracked");
+			} else {
+				db.vacuum().await?;
+				info!("vacuumed database");
+			}
+		}
+		Ok(())
+	}
+
+	/// Notifies about goals whose limit was crossed today and that haven't been notified about yet.
+	async fn check_goals(
+		&mut self,
+		db: &Database,
+		kactivities_conn: &KActivitiesConnection,
+		notifier: &Notifier,
+	) -> Result<()> {
+		let today = Local::now().date_naive();
+		if self.goals_day != Some(today) {
+			self.goals_exceeded.clear();
+			self.goals_day = Some(today);
+		}
+
+		let summary = db
+			.get_summary(Some(start_of_day(&Local, today)), None)
+			.await?;
+		let mut resolved = Vec::new();
+		for (uuid, duration) in summary {
+			let activity_info = kactivities_conn.query_activity_info(uuid.clone()).await?;
+			resolved.push((uuid, activity_info.name, duration));
+		}
+
+		for (goal, limit) in &self.config.goals {
+			if self.goals_exceeded.contains(goal) {
+				continue;
+			}
+			let spent = resolved
+				.iter()
+				.filter(|(uuid, name, _)| glob_match(goal, uuid) || glob_match(goal, name))
+				.fold(Duration::zero(), |acc, (_, _, duration)| acc + *duration);
+			let limit = Duration::seconds(*limit as i64);
+			if spent > limit {
+				info!("daily goal for {goal} exceeded");
+				notifier.goal_exceeded(goal, limit, spent).await;
+				self.goals_exceeded.insert(goal.clone());
+			}
+		}
+		Ok(())
+	}
+
+	pub async fn run(mut self) -> Result<()> {
+		info!("starting daemon");
+
+		// bind before touching the database so a second daemon can't record anything
+		let listener = match &self.config.socket_path {
+			Some(path) => bind_socket_file(path)?,
+			None => match UnixListener::bind(SOCKET_NAME) {
+				Ok(listener) => listener,
+				Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+					anyhow::bail!("ktimetracker daemon is already running");
+				}
+				Err(e) => return Err(e).context("failed to bind to unix socket"),
+			},
+		};
+
+		let key = self
+			.config
+			.database_key_file
+			.as_deref()
+			.map(read_key_file)
+			.transpose()?;
+		let db = Arc::new(
+			Database::new(
+				&self.config.database_path,
+				DatabaseOptions {
+					summary_cache_size: self.config.summary_cache_size,
+					max_connections: self.config.db_max_connections,
+					busy_timeout: std::time::Duration::from_millis(self.config.busy_timeout),
+					min_duration: self.config.min_duration.into(),
+					key,
+					read_only: false,
+				},
+			)
+			.await?,
+		);
+		let repaired = db.repair().await?;
+		if repaired > 0 {
+			warn!("closed {repaired} sessions left open by an earlier crash");
+		}
+		// an idle period left open by a crash would otherwise swallow every later session
+		db.end_idle_period().await?;
+		db.start_run().await?;
+		// shared by everything on the session bus that doesn't need a connection of its own
+		let session_conn = zbus::Connection::session()
+			.await
+			.context("failed to connect to d-bus session bus")?;
+		let kactivities_conn = KActivitiesConnection::with_connection(
+			session_conn.clone(),
+			self.event_tx.clone(),
+			self.config.default_activity.clone(),
+		);
+
+		let mut signal_handle: JoinHandle<Result<()>> = tokio::spawn({
+			let event_tx = self.event_tx.clone();
+			async move {
+				let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+				let mut sigint = signal::unix::signal(signal::unix::SignalKind::interrupt())?;
+				let mut sighup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
 				loop {
-					let (mut stream, _addr) = listener.accept().await?;
-					let db = db.clone();
-					let kactivities_conn = kactivities_conn.clone();
+					tokio::select! {
+						_ = sigterm.recv() => break,
+						_ = sigint.recv() => break,
+						_ = sighup.recv() => {
+							let _ = event_tx.send(DaemonEvent::ReloadConfig);
+						},
+					};
+				}
+				trace!("got signal, saving state");
+				Ok(())
+			}
+		});
+
+		if let Some(addr) = self.config.metrics_addr {
+			let exporter = MetricsExporter::new(db.clone());
+			tokio::spawn(async move {
+				if let Err(e) = exporter.daemon(addr).await {
+					error!("metrics exporter failed: {e}");
+				}
+			});
+		}
+
+		// goals are reported through notifications even if switches aren't
+		let notifier = if self.config.notify || !self.config.goals.is_empty() {
+			match Notifier::new(&session_conn, kactivities_conn.clone()).await {
+				Ok(notifier) => Some(notifier),
+				// notifications were only wanted for goals, which aren't worth failing to start over
+				Err(e) if !self.config.notify => {
+					warn!("failed to set up notifications, goals won't be reported: {e:#}");
+					None
+				}
+				Err(e) => return Err(e),
+			}
+		} else {
+			None
+		};
+
+		let webhook = self
+			.config
+			.webhook_url
+			.clone()
+			.map(Webhook::new)
+			.transpose()?;
+
+		// the kwin script calls back to the name the d-bus service owns
+		let dbus_service =
+			match DBusService::new(&session_conn, db.clone(), kactivities_conn.clone()).await {
+				Ok(service) => {
+					let state_rx = self.state_tx.subscribe();
 					tokio::spawn(async move {
-						if let Err(e) = handle_unix_client(&mut stream, db, kactivities_conn).await
-						{
-							error!("error handling unix client: {e}");
-							let _ = stream.write_all(format!("Error: {e}\n").as_bytes()).await;
+						if let Err(e) = service.daemon(state_rx).await {
+							error!("d-bus service failed: {e}");
 						}
 					});
+					true
+				}
+				Err(e) => {
+					warn!("not serving the tracked activity on d-bus: {e:#}");
+					false
+				}
+			};
+
+		let kwin_conn = if self.config.track_windows && !dbus_service {
+			warn!("not tracking windows, kwin can't reach the daemon without its d-bus name");
+			None
+		} else if self.config.track_windows {
+			match KWinConnection::new(&session_conn, self.event_tx.clone()).await {
+				Ok(conn) => Some(conn),
+				Err(e) => {
+					warn!("kwin is unreachable, not tracking windows: {e}");
+					None
+				}
+			}
+		} else {
+			None
+		};
+
+		let initial_activity = kactivities_conn.query_current_activity().await?;
+		// a session left open by a daemon that didn't shut down cleanly is continued if the
+		// activity is still the same, instead of splitting it at the restart
+		self.apply_idle_timeout(&kactivities_conn, &initial_activity)
+			.await?;
+		if db.get_current_activity().await?.as_ref() == Some(&initial_activity)
+			&& !self
+				.is_excluded(&kactivities_conn, &initial_activity)
+				.await?
+		{
+			trace!("continuing open session of {initial_activity}");
+		} else {
+			self.switch_to(&db, &kactivities_conn, &initial_activity)
+				.await?;
+			trace!("kde activity changed to {initial_activity}");
+		}
+		self.publish_state(&db).await?;
+
+		let mut idle_handle = tokio::spawn(idle::run(
+			self.event_tx.clone(),
+			self.idle_timeout_tx.subscribe(),
+			IdleOptions {
+				seat: self.config.seat.clone(),
+			},
+		));
+
+		let mut systemd_handle = tokio::spawn(
+			SystemdConnection::new(self.event_tx.clone())
+				.await?
+				.daemon(),
+		);
+
+		let uid = current_uid()?;
+		let mut unix_socket_handle: JoinHandle<Result<()>> = tokio::spawn({
+			let ctx = ClientContext {
+				db: db.clone(),
+				activities: Arc::new(kactivities_conn.clone()),
+				clients: Arc::new(ClientRegistry::default()),
+				event_tx: self.event_tx.clone(),
+				state_rx: self.state_tx.subscribe(),
+				request_timeout: std::time::Duration::from_millis(self.config.request_timeout),
+				categories: self.categories_tx.subscribe(),
+			};
+			async move {
+				loop {
+					let (mut stream, _addr) = listener.accept().await?;
+					let cred = stream.peer_cred().ok();
+					let ctx = ctx.clone();
+					ctx.clients.clone().spawn(
+						cred.map(|x| x.uid()),
+						cred.and_then(|x| x.pid()),
+						async move {
+							// the abstract socket is reachable by every user, so only serve our own
+							if cred.map(|x| x.uid()) != Some(uid) {
+								warn!(
+									"refusing client of uid {:?}, the daemon runs as uid {uid}",
+									cred.map(|x| x.uid())
+								);
+								let _ = ResponseWriter::new(&mut stream)
+									.error("permission denied: the daemon belongs to another user")
+									.await;
+								return;
+							}
+							if let Err(e) = handle_unix_client(&mut stream, ctx).await {
+								error!("error handling unix client: {e}");
+								let _ =
+									ResponseWriter::new(&mut stream).error(&e.to_string()).await;
+							}
+						},
+					);
 				}
 			}
 		});
 
-		loop {
-			tokio::select! {
-				res = &mut signal_handle => {
-					debug!("terminating due to signal, save result {res:?}");
-					break;
-				},
-				res = &mut wayland_handle => {
-					error!("wayland task exited with: {res:?}");
-					break;
-				},
-				res = &mut systemd_handle => {
-					error!("systemd task exited with: {res:?}");
-					break;
-				},
-				res = &mut unix_socket_handle => {
-					error!("unix socket task exited with: {res:?}");
-					break;
-				},
-				event = self.event_rx.recv() => {
-					match event {
-						Some(DaemonEvent::KdeActivityChanged { activity }) => {
-							trace!("activity changed to {activity}");
-							db.switch_activity(&activity).await?;
+		let mut goal_check = tokio::time::interval(GOAL_CHECK_INTERVAL);
+		let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+		// the first run is one interval after starting, the database was just opened anyway
+		let maintenance_period =
+			std::time::Duration::from_secs(self.config.maintenance_interval.max(1));
+		let mut maintenance = tokio::time::interval_at(
+			tokio::time::Instant::now() + maintenance_period,
+			maintenance_period,
+		);
+
+		// all of this does nothing unless systemd started us with Type=notify
+		if std::env::var_os("NOTIFY_SOCKET").is_some() {
+			tokio::spawn(report_status(
+				self.state_tx.subscribe(),
+				kactivities_conn.clone(),
+			));
+		}
+		let mut watchdog_usec = 0;
+		let watchdog_enabled = sd_notify::watchdog_enabled(false, &mut watchdog_usec);
+		// ping at twice the required rate so a late tick doesn't trip the watchdog
+		let mut watchdog = tokio::time::interval(
+			std::time::Duration::from_micros(watchdog_usec / 2)
+				.max(std::time::Duration::from_millis(1)),
+		);
+		if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+			warn!("failed to notify systemd of readiness: {e}");
+		}
+
+		let result: Result<()> = async {
+			loop {
+				tokio::select! {
+					res = &mut signal_handle => {
+						debug!("terminating due to signal: {res:?}");
+						break;
+					},
+					res = &mut idle_handle => {
+						error!("idle detection task exited with: {res:?}");
+						break;
+					},
+					res = &mut systemd_handle => {
+						error!("systemd task exited with: {res:?}");
+						break;
+					},
+					res = &mut unix_socket_handle => {
+						error!("unix socket task exited with: {res:?}");
+						break;
+					},
+					_ = watchdog.tick(), if watchdog_enabled => {
+						let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+					},
+					_ = heartbeat.tick() => {
+						if let Err(e) = db.heartbeat().await {
+							warn!("failed to record heartbeat: {e}");
+						}
+					},
+					_ = maintenance.tick(), if self.config.maintenance_interval > 0 => {
+						if let Err(e) = self.maintain(&db).await {
+							warn!("database maintenance failed: {e}");
+						}
+					},
+					_ = goal_check.tick(), if !self.config.goals.is_empty() => {
+						if let Some(notifier) = &notifier
+							&& let Err(e) = self.check_goals(&db, &kactivities_conn, notifier).await
+						{
+							warn!("failed to check goals: {e}");
 						}
-						Some(DaemonEvent::IdleStatusChanged { idle }) => {
-							if idle {
-								trace!("ending current activity: now idle");
+					},
+					event = self.event_rx.recv() => {
+						match event {
+							Some(DaemonEvent::KdeActivityChanged { activity }) => {
+								let previous = db.get_current_activity_elapsed_time().await?;
+								if self.kde_activity_changed(&db, &kactivities_conn, &activity).await? {
+									send_webhook(webhook.as_ref(), &db, WebhookEvent::ActivityChanged).await;
+									if let Some(notifier) = &notifier {
+										if self.config.notify {
+											notifier.activity_switched(activity, previous);
+										}
+										if !self.config.goals.is_empty()
+											&& let Err(e) = self.check_goals(&db, &kactivities_conn, notifier).await
+										{
+											warn!("failed to check goals: {e}");
+										}
+									}
+								}
+							}
+							Some(DaemonEvent::IdleStatusChanged { idle }) if self.config.on_idle() == OnIdle::Keep => {
+								trace!("ignoring idle change to {idle}: keeping the session");
+							}
+							Some(DaemonEvent::IdleStatusChanged { idle }) => {
+								if idle && !self.idle {
+									// the user stopped giving input a whole timeout ago
+									let idle_timeout = *self.idle_timeout_tx.borrow();
+									db.start_idle_period(Utc::now() - Duration::milliseconds(idle_timeout.into()))
+										.await?;
+								} else if !idle && self.idle {
+									db.end_idle_period().await?;
+								}
+								self.idle = idle;
+								if idle {
+									if self.config.on_idle() == OnIdle::SwitchToIdleActivity
+										&& let Some(idle_activity) = &self.config.idle_activity
+										&& !self.paused
+									{
+										trace!("starting activity {idle_activity}: now idle");
+										db.switch_activity(idle_activity, None).await?;
+									} else {
+										trace!("ending current activity: now idle");
+										db.end_current_activity().await?;
+									}
+								} else if !self.paused && !self.locked {
+									let activity = self.tracked_activity(&kactivities_conn).await?;
+									trace!("starting activity {activity}: no longer idle");
+									self.switch_to(&db, &kactivities_conn, &activity).await?;
+								}
+								let event = if idle { WebhookEvent::Idle } else { WebhookEvent::Active };
+								send_webhook(webhook.as_ref(), &db, event).await;
+							}
+							Some(DaemonEvent::SleepingNow { inhibitor }) if self.config.on_sleep() == OnSleep::Keep => {
+								trace!("keeping the session through sleep");
+								drop(inhibitor);
+								send_webhook(webhook.as_ref(), &db, WebhookEvent::Sleep).await;
+							}
+							Some(DaemonEvent::SleepingNow { inhibitor }) => {
+								if self.config.on_sleep() == OnSleep::SwitchToSleepActivity
+									&& let Some(sleep_activity) = &self.config.sleep_activity
+									&& !self.paused
+								{
+									trace!("starting activity {sleep_activity}: now going to sleep");
+									db.switch_activity(sleep_activity, None).await?;
+								} else {
+									trace!("ending current activity: now going to sleep");
+									db.end_current_activity().await?;
+								}
+								// let suspend proceed now that the session is saved
+								drop(inhibitor);
+								send_webhook(webhook.as_ref(), &db, WebhookEvent::Sleep).await;
+							}
+							Some(DaemonEvent::WakingNow) => {
+								// with on_sleep keep the session never ended, so there's nothing to restart
+								if !self.paused && !self.locked && self.config.on_sleep() != OnSleep::Keep {
+									let activity = self.tracked_activity(&kactivities_conn).await?;
+									trace!("stating activity {activity}: no longer asleep");
+									self.switch_to(&db, &kactivities_conn, &activity).await?;
+								}
+								send_webhook(webhook.as_ref(), &db, WebhookEvent::Wake).await;
+							}
+							Some(DaemonEvent::ScreenLocked) => self.screen_locked(&db).await?,
+							Some(DaemonEvent::ScreenUnlocked) => {
+								self.locked = false;
+								if !self.paused {
+									let activity = self.tracked_activity(&kactivities_conn).await?;
+									trace!("starting activity {activity}: screen unlocked");
+									self.switch_to(&db, &kactivities_conn, &activity).await?;
+								}
+							}
+							Some(DaemonEvent::Pause) => {
+								trace!("ending current activity: paused");
+								self.paused = true;
 								db.end_current_activity().await?;
-							} else {
+							}
+							Some(DaemonEvent::Resume) => {
+								self.paused = false;
+								self.manual_activity = None;
 								let activity = kactivities_conn.query_current_activity().await?;
-								trace!("starting activity {activity}: no longer idle");
-								db.switch_activity(&activity).await?;
+								trace!("starting activity {activity}: resumed");
+								self.switch_to(&db, &kactivities_conn, &activity).await?;
+							}
+							Some(DaemonEvent::WindowChanged { app_id }) => {
+								trace!("focused app changed to {app_id:?}");
+								self.app_id = app_id;
+								db.switch_app(self.app_id.as_deref()).await?;
+							}
+							Some(DaemonEvent::Start { activity }) => {
+								trace!("starting manual activity {activity}");
+								self.paused = false;
+								self.apply_idle_timeout(&kactivities_conn, &activity).await?;
+								db.switch_activity(&activity, self.app_id.as_deref()).await?;
+								self.manual_activity = Some(activity);
+							}
+							Some(DaemonEvent::SourceStatus { source, connected }) => {
+								debug!("{source:?} connected: {connected}");
+								match source {
+									Source::Wayland => self.sources.wayland = connected,
+									Source::ScreenSaver => self.sources.screensaver = connected,
+									Source::Systemd => self.sources.systemd = connected,
+									Source::KActivities => self.sources.kactivities = connected,
+								}
+							}
+							Some(DaemonEvent::Status { reply }) => {
+								let _ = reply.send(DaemonStatus {
+									sources: self.sources.clone(),
+									tracking: self.state_tx.borrow().clone(),
+									database_path: self.config.database_path.clone(),
+								});
+							}
+							Some(DaemonEvent::ReloadConfig) => {
+								info!("reloading config");
+								if let Err(e) = self
+									.reload_config(&kactivities_conn, notifier.is_some())
+									.await
+								{
+									warn!("failed to reload config, keeping the old one: {e:#}");
+								}
+							}
+							None => {
+								break;
 							}
 						}
-						Some(DaemonEvent::SleepingNow) => {
-							trace!("ending current activity: now going to sleep");
-							db.end_current_activity().await?;
-						}
-						Some(DaemonEvent::WakingNow) => {
-							let activity = kactivities_conn.query_current_activity().await?;
-							trace!("stating activity {activity}: no longer asleep");
-							db.switch_activity(&activity).await?;
-						}
-						None => {
-							break;
-						}
+						self.publish_state(&db).await?;
 					}
 				}
 			}
+			Ok(())
 		}
+		.await;
+
+		let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
 
+		// every way out of the loop ends up here, so a clean shutdown never leaves a session open
+		if let Err(e) = db.end_current_activity().await {
+			error!("failed to end current activity on shutdown: {e}");
+		}
+		if let Err(e) = db.end_idle_period().await {
+			error!("failed to end idle period on shutdown: {e}");
+		}
+		if let Err(e) = db.end_run().await {
+			error!("failed to record shutdown: {e}");
+		}
+
+		if let Some(kwin_conn) = kwin_conn {
+			kwin_conn.close().await;
+		}
+		if let Some(path) = &self.config.socket_path {
+			let _ = std::fs::remove_file(path);
+		}
 		db.close().await;
-		Ok(())
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use futures::future::BoxFuture;
+	use protocol::FrameKind;
+	use tokio::io::AsyncReadExt;
+
+	use super::*;
+
+	/// Resolves activities from a fixed UUID to name map instead of KDE.
+	struct FakeResolver(HashMap<String, String>);
+
+	impl ActivityResolver for FakeResolver {
+		fn query_activities(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+			Box::pin(async { Ok(self.0.keys().cloned().collect()) })
+		}
+
+		fn query_activity_info(&self, activity: String) -> BoxFuture<'_, Result<ActivityInfo>> {
+			Box::pin(async move {
+				Ok(ActivityInfo {
+					name: self.0.get(&activity).cloned().unwrap_or_default(),
+					..Default::default()
+				})
+			})
+		}
+	}
+
+	async fn memory_db() -> Database {
+		Database::new(
+			":memory:",
+			DatabaseOptions {
+				summary_cache_size: 0,
+				max_connections: 1,
+				busy_timeout: std::time::Duration::from_secs(5),
+				min_duration: 0,
+				key: None,
+				read_only: false,
+			},
+		)
+		.await
+		.unwrap()
+	}
+
+	fn context(db: Database, activities: &[(&str, &str)]) -> ClientContext {
+		let resolver = FakeResolver(
+			activities
+				.iter()
+				.map(|(uuid, name)| (uuid.to_string(), name.to_string()))
+				.collect(),
+		);
+		ClientContext {
+			db: Arc::new(db),
+			activities: Arc::new(resolver),
+			clients: Arc::new(ClientRegistry::default()),
+			event_tx: mpsc::unbounded_channel().0,
+			state_rx: watch::channel(TrackingState::default()).1,
+			request_timeout: std::time::Duration::ZERO,
+			categories: watch::channel(Arc::new(BTreeMap::new())).1,
+		}
+	}
+
+	async fn render(ctx: &ClientContext, action: Action) -> String {
+		let mut out = Vec::new();
+		handle_action(
+			&mut ResponseWriter::unframed(&mut out),
+			ctx,
+			action,
+			&RenderOptions::default(),
+		)
+		.await
+		.unwrap();
+		String::from_utf8(out).unwrap()
+	}
+
+	fn local(y: i32, m: u32, d: u32, h: u32) -> i64 {
+		Local
+			.with_ymd_and_hms(y, m, d, h, 0, 0)
+			.unwrap()
+			.timestamp()
+	}
+
+	fn summary(start: &str, end: &str) -> Action {
+		Action::Summary {
+			period: None,
+			start_time: Some(start.to_string()),
+			end_time: Some(end.to_string()),
+			format: Format::Table,
+			top: None,
+			other: false,
+			filter: None,
+			group_by: GroupBy::Activity,
+			timezone: None,
+			no_header: false,
+			compare: false,
+			stats: false,
+			exclude_idle: false,
+			round_now: None,
+			coverage: false,
+		}
+	}
+
+	fn utc(s: &str) -> DateTime<Utc> {
+		s.parse().unwrap()
+	}
+
+	#[test]
+	fn periods_start_at_midnight_in_the_requested_zone() {
+		let berlin = chrono_tz::Europe::Berlin;
+		// DST starts at 02:00 that day, midnight is still CET
+		let now = utc("2024-03-31T10:00:00Z").with_timezone(&berlin);
+		let (start, end) = summary_window(Some(SummaryPeriod::Today), None, None, now).unwrap();
+		assert_eq!(start.unwrap(), utc("2024-03-30T23:00:00Z"));
+		assert_eq!(end, None);
+
+		let tokyo = chrono_tz::Asia::Tokyo;
+		let now = utc("2024-03-31T10:00:00Z").with_timezone(&tokyo);
+		let (start, _) = summary_window(Some(SummaryPeriod::Today), None, None, now).unwrap();
+		assert_eq!(start.unwrap(), utc("2024-03-30T15:00:00Z"));
+	}
+
+	#[test]
+	fn dates_are_parsed_in_the_requested_zone_across_dst() {
+		let berlin = chrono_tz::Europe::Berlin;
+		let now = utc("2024-11-01T12:00:00Z").with_timezone(&berlin);
+		let (start, end) = summary_window(
+			None,
+			Some("2024-10-27".to_string()),
+			Some("2024-10-28".to_string()),
+			now,
+		)
+		.unwrap();
+		let (start, end) = (start.unwrap(), end.unwrap());
+		assert_eq!(start, utc("2024-10-26T22:00:00Z"));
+		assert_eq!(end, utc("2024-10-27T23:00:00Z"));
+		// the day DST ends has an extra hour
+		assert_eq!(end - start, Duration::hours(25));
+	}
+
+	#[tokio::test]
+	async fn summary_table_resolves_activity_names() {
+		let db = memory_db().await;
+		db.insert_session(
+			"uuid-work",
+			local(2024, 6, 5, 9),
+			Some(local(2024, 6, 5, 11)),
+		)
+		.await;
+		db.insert_session(
+			"uuid-play",
+			local(2024, 6, 5, 11),
+			Some(local(2024, 6, 5, 12)),
+		)
+		.await;
+		// outside the window
+		db.insert_session(
+			"uuid-play",
+			local(2024, 6, 7, 9),
+			Some(local(2024, 6, 7, 10)),
+		)
+		.await;
+		let ctx = context(db, &[("uuid-work", "Work")]);
+
+		let output = render(&ctx, summary("2024-06-05", "2024-06-06")).await;
+		let rows: Vec<Vec<&str>> = output
+			.lines()
+			.map(|line| line.split_whitespace().filter(|x| *x != "|").collect())
+			.collect();
+		assert!(
+			rows.contains(&vec!["Work", "2h", "0m", "0s", "66.7%"]),
+			"{output}"
+		);
+		// activities KDE doesn't know are shown by UUID
+		assert!(
+			rows.contains(&vec!["uuid-play", "1h", "0m", "0s", "33.3%"]),
+			"{output}"
+		);
+		assert!(
+			rows.contains(&vec!["Total", "3h", "0m", "0s", "100.0%"]),
+			"{output}"
+		);
+		let work = output.find("Work").unwrap();
+		assert!(work < output.find("uuid-play").unwrap(), "{output}");
+	}
+
+	#[tokio::test]
+	async fn excluded_activities_are_never_recorded() {
+		let daemon = Daemon::new(
+			Config {
+				excluded_activities: vec!["Personal".to_string()],
+				..Default::default()
+			},
+			Box::new(|| Ok(Config::default())),
+		);
+		let db = memory_db().await;
+		let activities = FakeResolver(HashMap::from([(
+			"uuid-personal".to_string(),
+			"Personal".to_string(),
+		)]));
+
+		daemon
+			.switch_to(&db, &activities, "uuid-work")
+			.await
+			.unwrap();
+		daemon
+			.switch_to(&db, &activities, "uuid-personal")
+			.await
+			.unwrap();
+		assert_eq!(db.get_current_activity().await.unwrap(), None);
+		daemon
+			.switch_to(&db, &activities, "uuid-work")
+			.await
+			.unwrap();
+		assert_eq!(
+			db.get_current_activity().await.unwrap().as_deref(),
+			Some("uuid-work")
+		);
+
+		let names: Vec<_> = db
+			.get_events(50, 0)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|x| x.name)
+			.collect();
+		assert_eq!(names, ["uuid-work", "uuid-work"]);
+	}
+
+	#[tokio::test]
+	async fn december_31_can_be_in_week_1() {
+		let db = memory_db().await;
+		db.insert_session(
+			"a",
+			utc("2024-12-31T10:00:00Z").timestamp(),
+			Some(utc("2024-12-31T12:00:00Z").timestamp()),
+		)
+		.await;
+
+		let weekly = db
+			.get_weekly_summary(
+				Some(utc("2024-12-20T00:00:00Z").with_timezone(&Local)),
+				Some(utc("2025-01-10T00:00:00Z").with_timezone(&Local)),
+				Utc,
+			)
+			.await
+			.unwrap();
+		assert_eq!(weekly.len(), 1);
+		let (monday, name, duration) = &weekly[0];
+		assert_eq!(*monday, NaiveDate::from_ymd_opt(2024, 12, 30).unwrap());
+		assert_eq!((name.as_str(), *duration), ("a", Duration::hours(2)));
+		assert_eq!(iso_week_label(monday.iso_week()), "2025-W01");
+		assert_eq!(
+			iso_week_label(NaiveDate::from_ymd_opt(2024, 12, 31).unwrap().iso_week()),
+			"2025-W01"
+		);
+	}
+
+	#[test]
+	fn relative_and_absolute_dates_are_parsed() {
+		// a Thursday
+		let now = utc("2024-05-16T14:30:00Z");
+		let parse = |s: &str| parse_datetime_at(s, now).unwrap();
+
+		assert_eq!(parse("now"), now);
+		assert_eq!(parse("today"), utc("2024-05-16T00:00:00Z"));
+		assert_eq!(parse(" Yesterday "), utc("2024-05-15T00:00:00Z"));
+		assert_eq!(parse("this week"), utc("2024-05-13T00:00:00Z"));
+		assert_eq!(parse("7d"), utc("2024-05-09T14:30:00Z"));
+		assert_eq!(parse("24h"), utc("2024-05-15T14:30:00Z"));
+		assert_eq!(parse("30m"), utc("2024-05-16T14:00:00Z"));
+		assert_eq!(parse("2024-01-02 03:04:05"), utc("2024-01-02T03:04:05Z"));
+		assert_eq!(parse("2024-01-02"), utc("2024-01-02T00:00:00Z"));
+		assert_eq!(parse("02/01/2024"), utc("2024-01-02T00:00:00Z"));
+
+		assert!(parse_datetime_at("last tuesday", now).is_err());
+		assert!(parse_datetime_at("xd", now).is_err());
+	}
+
+	#[tokio::test]
+	async fn silent_clients_are_dropped() {
+		let mut ctx = context(memory_db().await, &[]);
+		ctx.request_timeout = std::time::Duration::from_millis(50);
+		let (mut server, mut client) = tokio::net::UnixStream::pair().unwrap();
+
+		let e = tokio::time::timeout(
+			std::time::Duration::from_secs(5),
+			handle_unix_client(&mut server, ctx),
+		)
+		.await
+		.expect("client was never dropped")
+		.unwrap_err();
+		assert_eq!(e.to_string(), "timed out waiting for request");
+
+		drop(server);
+		let mut buf = Vec::new();
+		assert_eq!(client.read_to_end(&mut buf).await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn own_clients_match_the_daemon_uid() {
+		let (server, _client) = tokio::net::UnixStream::pair().unwrap();
+		assert_eq!(server.peer_cred().unwrap().uid(), current_uid().unwrap());
+	}
+
+	#[tokio::test]
+	async fn activity_changes_are_ignored_while_locked() {
+		let mut daemon = Daemon::new(Config::default(), Box::new(|| Ok(Config::default())));
+		let db = memory_db().await;
+		let activities = FakeResolver(HashMap::new());
+
+		assert!(
+			daemon
+				.kde_activity_changed(&db, &activities, "uuid-work")
+				.await
+				.unwrap()
+		);
+		daemon.screen_locked(&db).await.unwrap();
+		assert!(
+			!daemon
+				.kde_activity_changed(&db, &activities, "uuid-personal")
+				.await
+				.unwrap()
+		);
+		assert_eq!(db.get_current_activity().await.unwrap(), None);
+	}
+
+	/// Sends `request` the way the client does and collects the frames of the response.
+	async fn round_trip(ctx: ClientContext, request: &Request) -> Vec<(FrameKind, String)> {
+		let (mut server, mut client) = tokio::net::UnixStream::pair().unwrap();
+		let daemon = tokio::spawn(async move { handle_unix_client(&mut server, ctx).await });
+		protocol::write_request(&mut client, &serde_json::to_vec(request).unwrap())
+			.await
+			.unwrap();
+		daemon.await.unwrap().unwrap();
+
+		let mut frames = Vec::new();
+		while let Some((kind, payload)) = protocol::read_frame(&mut client).await.unwrap() {
+			frames.push((kind, String::from_utf8(payload).unwrap()));
+		}
+		frames
+	}
+
+	#[tokio::test]
+	async fn batches_end_each_response() {
+		let ctx = context(memory_db().await, &[]);
+		let version = format!("daemon {VERSION}\n");
+
+		let single = round_trip(
+			ctx.clone(),
+			&Request {
+				action: Some(Action::Version),
+				actions: Vec::new(),
+				render: RenderOptions::default(),
+				version: VERSION.to_string(),
+			},
+		)
+		.await;
+		assert_eq!(single, [(FrameKind::Output, version.clone())]);
+
+		let batch = round_trip(
+			ctx,
+			&Request {
+				action: None,
+				actions: vec![
+					Action::Current {
+						format: Format::Json,
+					},
+					summary("not a date", "now"),
+					Action::Version,
+				],
+				render: RenderOptions::default(),
+				version: VERSION.to_string(),
+			},
+		)
+		.await;
+		let kinds: Vec<_> = batch.iter().map(|(kind, _)| *kind).collect();
+		assert_eq!(
+			kinds,
+			[
+				FrameKind::Output,
+				FrameKind::End,
+				FrameKind::Error,
+				FrameKind::End,
+				FrameKind::Output,
+				FrameKind::End,
+			]
+		);
+		assert_eq!(batch[0].1, "null\n");
+		assert_eq!(batch[4].1, version);
+
+		// the example from the README
+		let example: Request = serde_json::from_str(
+			r#"{"actions": [{"Current": {"format": "Json"}}, {"Summary": {"period": "Today", "format": "Json", "other": false, "group_by": "Activity", "no_header": false, "compare": false, "stats": false, "exclude_idle": false, "coverage": false}}]}"#,
+		)
+		.unwrap();
+		assert_eq!(example.actions.len(), 2);
 	}
 }
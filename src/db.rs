@@ -1,87 +1,566 @@
-use anyhow::Result;
-use chrono::{DateTime, Duration, Local, Utc};
-use log::info;
-use sqlx::{FromRow, Sqlite, migrate::MigrateDatabase, sqlite::SqlitePool};
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike, Utc};
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use sqlx::{
+	FromRow,
+	sqlite::{
+		SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+	},
+};
+use std::{collections::VecDeque, path::Path, str::FromStr, sync::Mutex, time::Instant};
+
+use crate::util::start_of_day;
+
+/// Whether `database_url` names an in-memory database rather than a file.
+pub fn is_in_memory(database_url: &str) -> bool {
+	matches!(database_url, ":memory:" | "sqlite::memory:")
+}
 
 pub struct Database {
 	pool: SqlitePool,
+	/// When the open session started by this process began, on the monotonic clock.
+	session_started: Mutex<Option<(i64, Instant)>>,
+	summary_cache: Mutex<SummaryCache>,
+	min_duration: i64,
+}
+
+pub struct DatabaseOptions {
+	/// Number of summaries to cache, 0 disables the cache.
+	pub summary_cache_size: usize,
+	pub max_connections: u32,
+	/// How long a connection waits for another one holding the write lock before giving up.
+	pub busy_timeout: std::time::Duration,
+	/// Sessions shorter than this many seconds are deleted when they end, 0 keeps all of them.
+	pub min_duration: i64,
+	/// SQLCipher key to decrypt the database with, only supported when built with `sqlcipher`.
+	pub key: Option<String>,
+	/// Open an existing database without ever writing to it, or migrating it.
+	pub read_only: bool,
+}
+
+/// Reads a SQLCipher key from `path`, ignoring a trailing newline.
+pub fn read_key_file(path: &Path) -> Result<String> {
+	std::fs::read_to_string(path)
+		.map(|x| x.trim_end_matches(['\r', '\n']).to_string())
+		.with_context(|| format!("failed to read database key from {}", path.display()))
+}
+
+/// Whether `e` is SQLite's SQLITE_NOTADB, which is what a wrong SQLCipher key looks like.
+fn is_not_a_database(e: &sqlx::Error) -> bool {
+	matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("26"))
+}
+
+/// Whether `e` is SQLite's SQLITE_BUSY or SQLITE_LOCKED, e.g. from a VACUUM or backup holding
+/// the database for longer than the busy timeout.
+fn is_locked(e: &anyhow::Error) -> bool {
+	matches!(
+		e.downcast_ref::<sqlx::Error>(),
+		Some(sqlx::Error::Database(e)) if e
+			.code()
+			.and_then(|code| code.parse::<i32>().ok())
+			// extended codes like SQLITE_BUSY_SNAPSHOT keep the primary code in the low byte
+			.is_some_and(|code| matches!(code & 0xff, 5 | 6))
+	)
+}
+
+/// Attempts after the first at writes that find the database locked.
+const LOCK_RETRIES: u32 = 3;
+
+/// Runs `f` again with backoff while it fails because the database is locked, so a transition
+/// isn't lost to a lock that outlasted the busy timeout.
+async fn retry_locked<T, F: Future<Output = Result<T>>>(mut f: impl FnMut() -> F) -> Result<T> {
+	let mut attempt = 0;
+	loop {
+		match f().await {
+			Err(e) if attempt < LOCK_RETRIES && is_locked(&e) => {
+				attempt += 1;
+				let backoff = std::time::Duration::from_millis(250 << attempt);
+				warn!("database is locked, retrying in {backoff:?}: {e:#}");
+				tokio::time::sleep(backoff).await;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// Summaries of windows that lie entirely in the past, most recently used first. Anything that
+/// modifies sessions clears it, so cached results are never stale.
+struct SummaryCache {
+	capacity: usize,
+	/// bumped on every invalidation, so a summary computed while sessions changed isn't cached
+	generation: u64,
+	entries: VecDeque<(Window, Vec<(String, Duration)>)>,
+}
+
+/// Start and end of a cached summary, as UTC timestamps.
+type Window = (i64, i64);
+
+impl SummaryCache {
+	fn get(&mut self, window: Window) -> Option<Vec<(String, Duration)>> {
+		let i = self.entries.iter().position(|(x, _)| *x == window)?;
+		let entry = self.entries.remove(i)?;
+		let summary = entry.1.clone();
+		self.entries.push_front(entry);
+		Some(summary)
+	}
+
+	fn insert(&mut self, generation: u64, window: Window, summary: Vec<(String, Duration)>) {
+		if self.capacity == 0 || generation != self.generation {
+			return;
+		}
+		self.entries.push_front((window, summary));
+		self.entries.truncate(self.capacity);
+	}
 }
 
+/// How far the wall clock may fall behind the monotonic clock before it's considered to have
+/// jumped backwards.
+const CLOCK_JUMP_TOLERANCE: i64 = 5;
+
 #[derive(Debug, FromRow)]
-struct Activity {
-	#[allow(dead_code)]
-	id: i64,
-	name: String,
-	start_time: i64,
-	end_time: Option<i64>,
+pub struct Activity {
+	pub id: i64,
+	pub name: String,
+	pub start_time: i64,
+	pub end_time: Option<i64>,
+	/// app focused during the session, only recorded when window tracking is enabled
+	pub app_id: Option<String>,
+	/// free text attached with `note`
+	pub note: Option<String>,
+}
+
+/// Sessions of one activity within a summary window.
+#[derive(Debug)]
+pub struct SessionStats {
+	pub name: String,
+	pub sessions: u64,
+	pub total: Duration,
+	pub longest: Duration,
 }
 
+impl SessionStats {
+	pub fn average(&self) -> Duration {
+		self.total / self.sessions.max(1) as i32
+	}
+}
+
+/// Schema migrations, applied in order. After applying a migration the database's `user_version`
+/// is its index plus one, so existing entries must never be edited or reordered.
+const MIGRATIONS: &[&str] = &[
+	// databases created before migrations existed already have this table but are at version 0
+	r#"
+    CREATE TABLE IF NOT EXISTS activities (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        start_time INTEGER NOT NULL,
+        end_time INTEGER
+    );
+    "#,
+	r#"
+    ALTER TABLE activities ADD COLUMN app_id TEXT;
+    "#,
+	r#"
+    ALTER TABLE activities ADD COLUMN note TEXT;
+    "#,
+	r#"
+    CREATE TABLE idle_periods (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        start_time INTEGER NOT NULL,
+        end_time INTEGER
+    );
+    "#,
+	// end_time stays NULL if the daemon didn't shut down cleanly, last_seen bounds it then
+	r#"
+    CREATE TABLE daemon_runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        start_time INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        end_time INTEGER
+    );
+    "#,
+];
+
+/// How often the daemon records that it's still running.
+pub const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 impl Database {
-	pub async fn new(database_url: &str) -> Result<Self> {
-		info!("opening database at {database_url}");
-		if !Sqlite::database_exists(&database_url).await? {
-			Sqlite::create_database(&database_url).await?;
-		}
-		let pool = SqlitePool::connect(database_url).await?;
+	/// `:memory:` and `sqlite::memory:` open a database that only lives as long as the process.
+	pub async fn new(database_url: &str, options: DatabaseOptions) -> Result<Self> {
+		let pool = if is_in_memory(database_url) {
+			info!("opening in-memory database, nothing will be kept after exit");
+			// every connection to `:memory:` gets its own database, so keep exactly one open forever
+			SqlitePoolOptions::new()
+				.max_connections(1)
+				.min_connections(1)
+				.idle_timeout(None)
+				.max_lifetime(None)
+				.connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+				.await?
+		} else {
+			info!("opening database at {database_url}");
+			// accept both `sqlite://` urls and plain filesystem paths
+			let mut connect_options = if database_url.starts_with("sqlite:") {
+				SqliteConnectOptions::from_str(database_url)?
+			} else {
+				SqliteConnectOptions::new().filename(database_url)
+			}
+			.busy_timeout(options.busy_timeout);
+			connect_options = if options.read_only {
+				connect_options.read_only(true)
+			} else {
+				// WAL lets summaries read while a session is being written
+				connect_options
+					.create_if_missing(true)
+					.journal_mode(SqliteJournalMode::Wal)
+			};
+			if let Some(key) = &options.key {
+				if !cfg!(feature = "sqlcipher") {
+					anyhow::bail!(
+						"a database key was given but this build has no encryption support, rebuild with --features sqlcipher"
+					);
+				}
+				// sqlx always sends the key before any other pragma, as SQLCipher requires
+				connect_options =
+					connect_options.pragma("key", format!("'{}'", key.replace('\'', "''")));
+			}
+			match SqlitePoolOptions::new()
+				.max_connections(options.max_connections)
+				.connect_with(connect_options)
+				.await
+			{
+				Ok(pool) => pool,
+				Err(e) if options.key.is_some() && is_not_a_database(&e) => {
+					return Err(e).context("failed to decrypt database, is the key right?");
+				}
+				Err(e) => return Err(e.into()),
+			}
+		};
 
-		let db = Database { pool };
-		db.setup().await?;
+		let db = Database {
+			pool,
+			session_started: Mutex::new(None),
+			summary_cache: Mutex::new(SummaryCache {
+				capacity: options.summary_cache_size,
+				generation: 0,
+				entries: VecDeque::new(),
+			}),
+			min_duration: options.min_duration,
+		};
+		db.setup(options.read_only).await?;
 		Ok(db)
 	}
 
-	async fn setup(&self) -> Result<()> {
+	async fn setup(&self, read_only: bool) -> Result<()> {
+		let (version,): (i64,) = sqlx::query_as("PRAGMA user_version;")
+			.fetch_one(&self.pool)
+			.await?;
+		if version as usize > MIGRATIONS.len() {
+			anyhow::bail!(
+				"database schema version {version} is newer than this build of ktimetracker supports"
+			);
+		}
+		if read_only && (version as usize) < MIGRATIONS.len() {
+			anyhow::bail!(
+				"database schema version {version} is outdated, run the daemon on it once to migrate it"
+			);
+		}
+
+		for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+			let version = i + 1;
+			info!("migrating database to schema version {version}");
+
+			let mut tx = self.pool.begin().await?;
+			sqlx::raw_sql(migration).execute(&mut *tx).await?;
+			sqlx::query(&format!("PRAGMA user_version = {version};"))
+				.execute(&mut *tx)
+				.await?;
+			tx.commit().await?;
+		}
+		Ok(())
+	}
+
+	fn invalidate_summaries(&self) {
+		let mut cache = self.summary_cache.lock().expect("summary cache poisoned");
+		cache.generation += 1;
+		cache.entries.clear();
+	}
+
+	/// Timestamp to end the open session at. The wall clock can step backwards (NTP, manual
+	/// changes), which would make the session end before it started and drop out of summaries,
+	/// so the session is never made shorter than the monotonic clock says it lasted. The wall
+	/// clock still wins when it's ahead, since the monotonic clock stops during suspend.
+	fn end_timestamp(&self) -> i64 {
+		let now = Utc::now().timestamp();
+		let started = *self.session_started.lock().expect("session clock poisoned");
+		let Some((start_time, instant)) = started else {
+			return now;
+		};
+
+		let monotonic = start_time + instant.elapsed().as_secs() as i64;
+		if now < monotonic - CLOCK_JUMP_TOLERANCE {
+			warn!(
+				"wall clock jumped back by {}s, ending session using the monotonic clock",
+				monotonic - now
+			);
+		}
+		now.max(monotonic)
+	}
+
+	/// Start of a new session on both clocks, only to be recorded with [`Self::set_session_started`]
+	/// once the session is committed. It starts where the previous one ended, which after the
+	/// wall clock jumped back is later than now, so the two never overlap.
+	fn start_timestamp(previous_end: i64) -> (i64, Instant) {
+		(previous_end, Instant::now())
+	}
+
+	fn set_session_started(&self, started: Option<(i64, Instant)>) {
+		*self.session_started.lock().expect("session clock poisoned") = started;
+	}
+
+	/// Ends the open session at `end_time`, or deletes it if it's shorter than `min_duration`.
+	async fn close_open_session(&self, conn: &mut SqliteConnection, end_time: i64) -> Result<()> {
+		if self.min_duration > 0 {
+			sqlx::query(
+				r#"
+                DELETE FROM activities
+                WHERE end_time IS NULL AND MAX(?, start_time) - start_time < ?;
+                "#,
+			)
+			.bind(end_time)
+			.bind(self.min_duration)
+			.execute(&mut *conn)
+			.await?;
+		}
+		// sessions not started by this process have no monotonic start, so at least never let
+		// them end before they started
 		sqlx::query(
 			r#"
-            CREATE TABLE IF NOT EXISTS activities (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                start_time INTEGER NOT NULL,
-                end_time INTEGER
-            );
+            UPDATE activities
+            SET end_time = MAX(?, start_time)
+            WHERE end_time IS NULL;
             "#,
 		)
-		.execute(&self.pool)
+		.bind(end_time)
+		.execute(&mut *conn)
 		.await?;
 		Ok(())
 	}
 
 	pub async fn end_current_activity(&self) -> Result<()> {
-		let timestamp = Utc::now().timestamp();
+		let timestamp = self.end_timestamp();
+		retry_locked(|| async move {
+			let mut tx = self.pool.begin().await?;
+			self.close_open_session(&mut tx, timestamp).await?;
+			tx.commit().await?;
+			Ok(())
+		})
+		.await?;
+		self.set_session_started(None);
+		self.invalidate_summaries();
+		Ok(())
+	}
+
+	/// Records that the daemon started. If the previous run didn't shut down cleanly and hasn't
+	/// been seen for longer than a couple of heartbeats, the session it left open is ended at its
+	/// last heartbeat so the downtime isn't counted. After a quick restart the session is left
+	/// open to be continued.
+	pub async fn start_run(&self) -> Result<()> {
+		let now = Utc::now().timestamp();
+		let previous: Option<(i64, Option<i64>)> =
+			sqlx::query_as("SELECT last_seen, end_time FROM daemon_runs ORDER BY id DESC LIMIT 1;")
+				.fetch_optional(&self.pool)
+				.await?;
+		if let Some((last_seen, None)) = previous
+			&& now - last_seen > 2 * HEARTBEAT_INTERVAL.as_secs() as i64
+		{
+			let result = sqlx::query(
+				"UPDATE activities SET end_time = MAX(start_time, ?) WHERE end_time IS NULL;",
+			)
+			.bind(last_seen)
+			.execute(&self.pool)
+			.await?;
+			self.invalidate_summaries();
+			if result.rows_affected() > 0 {
+				warn!(
+					"the daemon stopped unexpectedly {}s ago, ended its session at the last heartbeat",
+					now - last_seen
+				);
+			}
+		}
+
+		sqlx::query("INSERT INTO daemon_runs (start_time, last_seen) VALUES (?, ?);")
+			.bind(now)
+			.bind(now)
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
+
+	pub async fn heartbeat(&self) -> Result<()> {
 		sqlx::query(
-			r#"
-            UPDATE activities
-            SET end_time = ?
-            WHERE end_time IS NULL;
-            "#,
+			"UPDATE daemon_runs SET last_seen = ? WHERE id = (SELECT MAX(id) FROM daemon_runs);",
 		)
-		.bind(timestamp)
+		.bind(Utc::now().timestamp())
 		.execute(&self.pool)
 		.await?;
 		Ok(())
 	}
 
-	pub async fn switch_activity(&self, new_activity: &str) -> Result<()> {
-		self.end_current_activity().await?;
-
-		let timestamp = Utc::now().timestamp();
+	/// Marks the current run as having shut down cleanly.
+	pub async fn end_run(&self) -> Result<()> {
+		let now = Utc::now().timestamp();
 		sqlx::query(
+			"UPDATE daemon_runs SET last_seen = ?1, end_time = ?1 WHERE id = (SELECT MAX(id) FROM daemon_runs);",
+		)
+		.bind(now)
+		.execute(&self.pool)
+		.await?;
+		Ok(())
+	}
+
+	/// Time within the window the daemon wasn't running, between the end of one run (or its last
+	/// heartbeat if it crashed) and the start of the next. Only gaps since runs were first
+	/// recorded are known.
+	pub async fn get_downtime(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<Duration> {
+		let start_time = start_time.map_or(0, |dt| dt.timestamp());
+		let end_time = end_time.map_or_else(|| Utc::now().timestamp(), |dt| dt.timestamp());
+
+		let (seconds,): (Option<i64>,) = sqlx::query_as(
 			r#"
-            INSERT INTO activities (name, start_time)
-            VALUES (?, ?);
+            SELECT SUM(MAX(0, MIN(start_time, ?1) - MAX(previous_end, ?2)))
+            FROM (
+                SELECT start_time, LAG(COALESCE(end_time, last_seen)) OVER (ORDER BY id) AS previous_end
+                FROM daemon_runs
+            )
+            WHERE previous_end IS NOT NULL;
             "#,
 		)
-		.bind(new_activity)
-		.bind(timestamp)
-		.execute(&self.pool)
+		.bind(end_time)
+		.bind(start_time)
+		.fetch_one(&self.pool)
 		.await?;
+		Ok(Duration::seconds(seconds.unwrap_or(0)))
+	}
+
+	/// Records that the user went idle at `start_time`, which is before the idle timeout fired and
+	/// so usually inside the session that was running then. Any idle period still open is closed.
+	pub async fn start_idle_period(&self, start_time: DateTime<Utc>) -> Result<()> {
+		self.end_idle_period().await?;
+		let start_time = start_time.timestamp();
+		retry_locked(|| async move {
+			sqlx::query("INSERT INTO idle_periods (start_time) VALUES (?);")
+				.bind(start_time)
+				.execute(&self.pool)
+				.await?;
+			Ok(())
+		})
+		.await
+	}
+
+	pub async fn end_idle_period(&self) -> Result<()> {
+		let now = Utc::now().timestamp();
+		retry_locked(|| async move {
+			sqlx::query(
+				"UPDATE idle_periods SET end_time = MAX(start_time, ?) WHERE end_time IS NULL;",
+			)
+			.bind(now)
+			.execute(&self.pool)
+			.await?;
+			Ok(())
+		})
+		.await
+	}
+
+	pub async fn switch_activity(&self, new_activity: &str, app_id: Option<&str>) -> Result<()> {
+		// taken once so retries don't move the transition
+		let end_time = self.end_timestamp();
+		let started = Self::start_timestamp(end_time);
+		let start_time = started.0;
+		retry_locked(|| async move {
+			let mut tx = self.pool.begin().await?;
+			self.close_open_session(&mut tx, end_time).await?;
+			sqlx::query(
+				r#"
+                INSERT INTO activities (name, start_time, app_id)
+                VALUES (?, ?, ?);
+                "#,
+			)
+			.bind(new_activity)
+			.bind(start_time)
+			.bind(app_id)
+			.execute(&mut *tx)
+			.await?;
+			tx.commit().await?;
+			Ok(())
+		})
+		.await?;
+		self.set_session_started(Some(started));
+		self.invalidate_summaries();
+		Ok(())
+	}
+
+	/// Splits the open session so time from now on is attributed to `app_id`. Does nothing if
+	/// nothing is being tracked or the app didn't change.
+	pub async fn switch_app(&self, app_id: Option<&str>) -> Result<()> {
+		// taken once so retries don't move the transition
+		let end_time = self.end_timestamp();
+		let started = Self::start_timestamp(end_time);
+		let switched = retry_locked(|| async move {
+			let mut tx = self.pool.begin().await?;
+			let current: Option<(String, Option<String>, Option<String>)> = sqlx::query_as(
+				r#"
+                SELECT name, app_id, note
+                FROM activities
+                WHERE end_time IS NULL
+                ORDER BY start_time DESC
+                LIMIT 1;
+                "#,
+			)
+			.fetch_optional(&mut *tx)
+			.await?;
+			let Some((name, current_app_id, note)) = current else {
+				return Ok(false);
+			};
+			if current_app_id.as_deref() == app_id {
+				return Ok(false);
+			}
+
+			self.close_open_session(&mut tx, end_time).await?;
+			sqlx::query(
+				r#"
+                INSERT INTO activities (name, start_time, app_id, note)
+                VALUES (?, ?, ?, ?);
+                "#,
+			)
+			.bind(name)
+			.bind(started.0)
+			.bind(app_id)
+			// both halves are still the same session
+			.bind(note)
+			.execute(&mut *tx)
+			.await?;
+			tx.commit().await?;
+			Ok(true)
+		})
+		.await?;
+		if !switched {
+			return Ok(());
+		}
+		self.set_session_started(Some(started));
+		self.invalidate_summaries();
 		Ok(())
 	}
 
-	pub async fn get_current_activity(&self) -> Result<String> {
-		let activity: Option<Activity> = sqlx::query_as(
+	/// Name of the open session, or `None` if nothing is being tracked.
+	pub async fn get_current_activity(&self) -> Result<Option<String>> {
+		let name: Option<(String,)> = sqlx::query_as(
 			r#"
-            SELECT id, name, start_time, end_time
+            SELECT name
             FROM activities
             WHERE end_time IS NULL
             ORDER BY start_time DESC
@@ -91,15 +570,14 @@ impl Database {
 		.fetch_optional(&self.pool)
 		.await?;
 
-		Ok(activity
-			.map(|a| a.name)
-			.unwrap_or_else(|| "No current activity".to_string()))
+		Ok(name.map(|(x,)| x))
 	}
 
-	pub async fn get_current_activity_elapsed_time(&self) -> Result<Option<Duration>> {
-		let activity: Option<Activity> = sqlx::query_as(
+	/// When the open session started, or `None` if nothing is being tracked.
+	pub async fn get_current_activity_start(&self) -> Result<Option<DateTime<Utc>>> {
+		let start_time: Option<(i64,)> = sqlx::query_as(
 			r#"
-            SELECT id, name, start_time, end_time
+            SELECT start_time
             FROM activities
             WHERE end_time IS NULL
             ORDER BY start_time DESC
@@ -109,37 +587,221 @@ impl Database {
 		.fetch_optional(&self.pool)
 		.await?;
 
-		match activity {
-			Some(act) => {
-				let start_time = DateTime::from_timestamp(act.start_time, 0)
-					.unwrap()
-					.with_timezone(&Utc);
-				let now = Utc::now();
-				Ok(Some(now - start_time))
+		Ok(start_time.and_then(|(x,)| DateTime::from_timestamp(x, 0)))
+	}
+
+	/// How long the open session has been running, or `None` if nothing is being tracked.
+	pub async fn get_current_activity_elapsed_time(&self) -> Result<Option<Duration>> {
+		Ok(self
+			.get_current_activity_start()
+			.await?
+			.map(|start_time| Utc::now() - start_time))
+	}
+
+	pub async fn get_summary(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<Vec<(String, Duration)>> {
+		// an unbounded summary covers all recorded history up to now
+		let start_time_utc = start_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or(DateTime::UNIX_EPOCH);
+		let end_time_utc = end_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or_else(Utc::now);
+
+		// windows reaching into the future change every second, so only past ones are cached
+		let window = (start_time_utc.timestamp(), end_time_utc.timestamp());
+		let cacheable = end_time.is_some() && window.1 <= Utc::now().timestamp();
+		let generation = {
+			let mut cache = self.summary_cache.lock().expect("summary cache poisoned");
+			if cacheable && let Some(summary) = cache.get(window) {
+				return Ok(summary);
 			}
-			None => Ok(None),
+			cache.generation
+		};
+
+		// each session is clamped to the window, with an open session running until the window end
+		let time_spent: Vec<(String, i64)> = sqlx::query_as(
+			r#"
+            SELECT name, SUM(MAX(0, MIN(COALESCE(end_time, ?1), ?1) - MAX(start_time, ?2))) AS seconds
+            FROM activities
+            WHERE start_time < ?1 AND (end_time IS NULL OR end_time > ?2)
+            GROUP BY name
+            HAVING seconds > 0
+            ORDER BY seconds DESC, name ASC;
+            "#,
+		)
+		.bind(end_time_utc.timestamp())
+		.bind(start_time_utc.timestamp())
+		.fetch_all(&self.pool)
+		.await?;
+
+		let summary: Vec<_> = time_spent
+			.into_iter()
+			.map(|(name, seconds)| (name, Duration::seconds(seconds)))
+			.collect();
+		if cacheable {
+			self.summary_cache
+				.lock()
+				.expect("summary cache poisoned")
+				.insert(generation, window, summary.clone());
 		}
+		Ok(summary)
 	}
 
-	pub async fn get_summary(
+	/// Like [`Database::get_summary`], but split into days in `tz`. Sessions spanning midnight count
+	/// towards every day they overlap, so the days always add up to the ungrouped summary.
+	pub async fn get_daily_summary<Tz>(
 		&self,
 		start_time: Option<DateTime<Local>>,
 		end_time: Option<DateTime<Local>>,
-	) -> Result<HashMap<String, Duration>> {
-		let mut time_spent: HashMap<String, Duration> = HashMap::new();
+		tz: Tz,
+	) -> Result<Vec<(NaiveDate, String, Duration)>>
+	where
+		Tz: TimeZone + Send,
+		Tz::Offset: Send,
+	{
+		self.get_split_summary(start_time, end_time, tz, |date| date, 1)
+			.await
+	}
+
+	/// Like [`Database::get_daily_summary`], but split into ISO weeks, each keyed by its Monday.
+	/// The Monday's `iso_week()` is the week's label, which near new year can belong to the year
+	/// before or after the one most of its days are in.
+	pub async fn get_weekly_summary<Tz>(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+		tz: Tz,
+	) -> Result<Vec<(NaiveDate, String, Duration)>>
+	where
+		Tz: TimeZone + Send,
+		Tz::Offset: Send,
+	{
+		let monday = |date: NaiveDate| {
+			date - chrono::Days::new(date.weekday().num_days_from_monday() as u64)
+		};
+		self.get_split_summary(start_time, end_time, tz, monday, 7)
+			.await
+	}
+
+	/// Summaries of consecutive periods of `days` days in `tz`, the first starting at `align` of
+	/// the window's start date.
+	async fn get_split_summary<Tz>(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+		tz: Tz,
+		align: fn(NaiveDate) -> NaiveDate,
+		days: u64,
+	) -> Result<Vec<(NaiveDate, String, Duration)>>
+	where
+		Tz: TimeZone + Send,
+		Tz::Offset: Send,
+	{
+		// don't walk every day since the epoch for an unbounded summary
+		let start_time = match start_time {
+			Some(start_time) => start_time,
+			None => match self.first_start_time().await? {
+				Some(first) => first.with_timezone(&Local),
+				None => return Ok(Vec::new()),
+			},
+		};
+		let end_time = end_time.unwrap_or_else(Local::now);
+
+		let mut summary = Vec::new();
+		let mut date = align(start_time.with_timezone(&tz).date_naive());
+		loop {
+			let day_start = start_of_day(&tz, date)
+				.with_timezone(&Local)
+				.max(start_time);
+			if day_start >= end_time {
+				break;
+			}
+			let next = date
+				.checked_add_days(chrono::Days::new(days))
+				.context("summary window is out of range")?;
+			let day_end = start_of_day(&tz, next).with_timezone(&Local).min(end_time);
 
+			for (name, duration) in self.get_summary(Some(day_start), Some(day_end)).await? {
+				summary.push((date, name, duration));
+			}
+			date = next;
+		}
+		Ok(summary)
+	}
+
+	pub async fn first_start_time(&self) -> Result<Option<DateTime<Utc>>> {
+		let (start_time,): (Option<i64>,) =
+			sqlx::query_as("SELECT MIN(start_time) FROM activities;")
+				.fetch_one(&self.pool)
+				.await?;
+		Ok(start_time.and_then(|x| DateTime::from_timestamp(x, 0)))
+	}
+
+	/// Idle time recorded within each activity's sessions, clamped to the window like
+	/// [`Database::get_summary`] so the two can be subtracted.
+	pub async fn get_idle_summary(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<Vec<(String, Duration)>> {
 		let start_time_utc = start_time
 			.map(|dt| dt.with_timezone(&Utc))
-			.unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap().with_timezone(&Utc));
+			.unwrap_or(DateTime::UNIX_EPOCH);
 		let end_time_utc = end_time
 			.map(|dt| dt.with_timezone(&Utc))
 			.unwrap_or_else(Utc::now);
 
-		let activities: Vec<Activity> = sqlx::query_as(
+		let idle: Vec<(String, i64)> = sqlx::query_as(
 			r#"
-            SELECT id, name, start_time, end_time
+            SELECT a.name, SUM(MAX(0,
+                MIN(COALESCE(a.end_time, ?1), COALESCE(i.end_time, ?1), ?1)
+                - MAX(a.start_time, i.start_time, ?2)
+            )) AS seconds
+            FROM activities AS a
+            JOIN idle_periods AS i
+                ON i.start_time < COALESCE(a.end_time, ?1)
+                AND (i.end_time IS NULL OR i.end_time > a.start_time)
+            WHERE a.start_time < ?1 AND (a.end_time IS NULL OR a.end_time > ?2)
+            GROUP BY a.name
+            HAVING seconds > 0;
+            "#,
+		)
+		.bind(end_time_utc.timestamp())
+		.bind(start_time_utc.timestamp())
+		.fetch_all(&self.pool)
+		.await?;
+
+		Ok(idle
+			.into_iter()
+			.map(|(name, seconds)| (name, Duration::seconds(seconds)))
+			.collect())
+	}
+
+	/// Like [`Database::get_summary`], but broken down by the app focused within each activity.
+	pub async fn get_app_summary(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<Vec<(String, Option<String>, Duration)>> {
+		let start_time_utc = start_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or(DateTime::UNIX_EPOCH);
+		let end_time_utc = end_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or_else(Utc::now);
+
+		let time_spent: Vec<(String, Option<String>, i64)> = sqlx::query_as(
+			r#"
+            SELECT name, app_id, SUM(MAX(0, MIN(COALESCE(end_time, ?1), ?1) - MAX(start_time, ?2))) AS seconds
             FROM activities
-            WHERE start_time < ? AND (end_time IS NULL OR end_time > ?);
+            WHERE start_time < ?1 AND (end_time IS NULL OR end_time > ?2)
+            GROUP BY name, app_id
+            HAVING seconds > 0
+            ORDER BY seconds DESC, name ASC, app_id ASC;
             "#,
 		)
 		.bind(end_time_utc.timestamp())
@@ -147,29 +809,1061 @@ impl Database {
 		.fetch_all(&self.pool)
 		.await?;
 
-		for activity in activities {
-			let activity_start = DateTime::from_timestamp(activity.start_time, 0)
-				.unwrap()
-				.with_timezone(&Utc);
-			let activity_end = match activity.end_time {
-				Some(ts) => DateTime::from_timestamp(ts, 0).unwrap().with_timezone(&Utc),
-				None => end_time_utc,
-			};
+		Ok(time_spent
+			.into_iter()
+			.map(|(name, app_id, seconds)| (name, app_id, Duration::seconds(seconds)))
+			.collect())
+	}
+
+	/// Time spent in each hour of the day in `tz`, summed over the window, optionally only for
+	/// the activity `name`. Sessions are split at every hour boundary they cross.
+	pub async fn get_hourly_summary<Tz: TimeZone>(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+		name: Option<&str>,
+		tz: Tz,
+	) -> Result<[Duration; 24]> {
+		let start_time_utc = start_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or(DateTime::UNIX_EPOCH);
+		let end_time_utc = end_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or_else(Utc::now);
+
+		let sessions: Vec<(i64, i64)> = sqlx::query_as(
+			r#"
+            SELECT MAX(start_time, ?2), MIN(COALESCE(end_time, ?1), ?1)
+            FROM activities
+            WHERE start_time < ?1 AND (end_time IS NULL OR end_time > ?2)
+                AND (?3 IS NULL OR name = ?3);
+            "#,
+		)
+		.bind(end_time_utc.timestamp())
+		.bind(start_time_utc.timestamp())
+		.bind(name)
+		.fetch_all(&self.pool)
+		.await?;
+
+		let mut hours = [Duration::zero(); 24];
+		for (start, end) in sessions {
+			let mut cursor = start;
+			while cursor < end {
+				let Some(local) = DateTime::from_timestamp(cursor, 0) else {
+					break;
+				};
+				let local = local.with_timezone(&tz);
+				// offsets aren't always whole hours, so find the next boundary in local time
+				let into_hour = i64::from(local.minute() * 60 + local.second());
+				let next = (cursor + 3600 - into_hour).min(end);
+				hours[local.hour() as usize] += Duration::seconds(next - cursor);
+				cursor = next;
+			}
+		}
+		Ok(hours)
+	}
+
+	/// Session count, total and longest session per activity, sorted like
+	/// [`Database::get_summary`] and with sessions clamped to the window the same way. Rows that
+	/// were only split because the focused app changed are counted as one session.
+	pub async fn get_session_stats(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<Vec<SessionStats>> {
+		let start_time_utc = start_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or(DateTime::UNIX_EPOCH);
+		let end_time_utc = end_time
+			.map(|dt| dt.with_timezone(&Utc))
+			.unwrap_or_else(Utc::now);
+
+		let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+			r#"
+            SELECT name, MAX(start_time, ?2), MIN(COALESCE(end_time, ?1), ?1)
+            FROM activities
+            WHERE start_time < ?1 AND (end_time IS NULL OR end_time > ?2)
+            ORDER BY start_time ASC, id ASC;
+            "#,
+		)
+		.bind(end_time_utc.timestamp())
+		.bind(start_time_utc.timestamp())
+		.fetch_all(&self.pool)
+		.await?;
+
+		// join rows of the same activity that follow each other without a gap
+		let mut sessions: Vec<(String, i64, i64)> = Vec::new();
+		for (name, start, end) in rows {
+			if let Some(last) = sessions.last_mut()
+				&& last.0 == name
+				&& last.2 == start
+			{
+				last.2 = end;
+			} else {
+				sessions.push((name, start, end));
+			}
+		}
+
+		let mut stats: Vec<SessionStats> = Vec::new();
+		for (name, start, end) in sessions {
+			if end <= start {
+				continue;
+			}
+			let duration = Duration::seconds(end - start);
+			match stats.iter_mut().find(|x| x.name == name) {
+				Some(entry) => {
+					entry.sessions += 1;
+					entry.total += duration;
+					entry.longest = entry.longest.max(duration);
+				}
+				None => stats.push(SessionStats {
+					name,
+					sessions: 1,
+					total: duration,
+					longest: duration,
+				}),
+			}
+		}
+		stats.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+		Ok(stats)
+	}
+
+	/// Renames every session of `old` to `new`, returning the number of sessions changed.
+	pub async fn rename_activity(&self, old: &str, new: &str) -> Result<u64> {
+		let mut tx = self.pool.begin().await?;
+		let result = sqlx::query(
+			r#"
+            UPDATE activities
+            SET name = ?
+            WHERE name = ?;
+            "#,
+		)
+		.bind(new)
+		.bind(old)
+		.execute(&mut *tx)
+		.await?;
+		tx.commit().await?;
+		self.invalidate_summaries();
+		Ok(result.rows_affected())
+	}
+
+	/// Closes every open session except the most recent one, which a crash can leave behind.
+	/// Each is ended where the session after it starts, so no time is counted twice. Returns the
+	/// number of sessions closed.
+	pub async fn repair(&self) -> Result<u64> {
+		let result = sqlx::query(
+			r#"
+            UPDATE activities AS a
+            SET end_time = (
+                SELECT MIN(b.start_time)
+                FROM activities AS b
+                WHERE b.start_time > a.start_time OR (b.start_time = a.start_time AND b.id > a.id)
+            )
+            WHERE a.end_time IS NULL AND a.id != (
+                SELECT id
+                FROM activities
+                WHERE end_time IS NULL
+                ORDER BY start_time DESC, id DESC
+                LIMIT 1
+            );
+            "#,
+		)
+		.execute(&self.pool)
+		.await?;
+		self.invalidate_summaries();
+		Ok(result.rows_affected())
+	}
+
+	/// Deletes every finished session that ended before `cutoff` and reclaims the freed space,
+	/// returning the number of sessions deleted. The open session is never deleted.
+	pub async fn purge_before(&self, cutoff: DateTime<Local>) -> Result<u64> {
+		let result = sqlx::query(
+			r#"
+            DELETE FROM activities
+            WHERE end_time IS NOT NULL AND end_time < ?;
+            "#,
+		)
+		.bind(cutoff.timestamp())
+		.execute(&self.pool)
+		.await?;
+		self.invalidate_summaries();
+		sqlx::query("DELETE FROM idle_periods WHERE end_time IS NOT NULL AND end_time < ?;")
+			.bind(cutoff.timestamp())
+			.execute(&self.pool)
+			.await?;
+
+		self.checkpoint().await?;
+		self.vacuum().await?;
+
+		Ok(result.rows_affected())
+	}
 
-			let effective_start = std::cmp::max(start_time_utc, activity_start);
-			let effective_end = std::cmp::min(end_time_utc, activity_end);
+	/// Moves everything in the WAL into the database file and truncates the WAL.
+	pub async fn checkpoint(&self) -> Result<()> {
+		sqlx::query("PRAGMA wal_checkpoint(TRUNCATE);")
+			.execute(&self.pool)
+			.await?;
+		Ok(())
+	}
 
-			let duration = effective_end - effective_start;
+	/// Rebuilds the database file to reclaim free pages. Blocks writers while it runs.
+	pub async fn vacuum(&self) -> Result<()> {
+		sqlx::query("VACUUM;").execute(&self.pool).await?;
+		Ok(())
+	}
+
+	/// Writes a snapshot of the database to `path` with `VACUUM INTO`, which is consistent even
+	/// while sessions are being written. Fails if `path` already exists.
+	pub async fn backup(&self, path: &str) -> Result<()> {
+		sqlx::query("VACUUM INTO ?;")
+			.bind(path)
+			.execute(&self.pool)
+			.await
+			.with_context(|| format!("failed to back up database to {path}"))?;
+		Ok(())
+	}
 
-			if duration > Duration::zero() {
-				*time_spent.entry(activity.name).or_insert(Duration::zero()) += duration;
+	/// Streams raw sessions overlapping the given window, oldest first. Rows are fetched as the
+	/// stream is polled, so it holds a pooled connection until it's dropped.
+	pub fn export_rows(
+		&self,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> impl Stream<Item = Result<Activity>> + '_ {
+		let start_time = start_time.map_or(0, |dt| dt.timestamp());
+		let end_time = end_time.map_or(i64::MAX, |dt| dt.timestamp());
+
+		sqlx::query_as::<_, Activity>(
+			r#"
+            SELECT id, name, start_time, end_time, app_id, note
+            FROM activities
+            WHERE start_time < ? AND (end_time IS NULL OR end_time > ?)
+            ORDER BY start_time, id;
+            "#,
+		)
+		.bind(end_time)
+		.bind(start_time)
+		.fetch(&self.pool)
+		.map(|row| row.map_err(anyhow::Error::from))
+	}
+
+	/// Activity, start and end of each session overlapping the window in chronological order,
+	/// clamped to the window, with an open session running until its end.
+	pub async fn get_timeline(
+		&self,
+		start_time: DateTime<Local>,
+		end_time: DateTime<Local>,
+	) -> Result<Vec<(String, i64, i64)>> {
+		Ok(sqlx::query_as(
+			r#"
+            SELECT name, MAX(start_time, ?2), MIN(COALESCE(end_time, ?1), ?1)
+            FROM activities
+            WHERE start_time < ?1 AND (end_time IS NULL OR end_time > ?2)
+            ORDER BY start_time, id;
+            "#,
+		)
+		.bind(end_time.timestamp())
+		.bind(start_time.timestamp())
+		.fetch_all(&self.pool)
+		.await?)
+	}
+
+	/// Sessions newest first, skipping the `offset` newest and returning at most `limit`.
+	pub async fn get_events(&self, limit: i64, offset: i64) -> Result<Vec<Activity>> {
+		Ok(sqlx::query_as(
+			r#"
+            SELECT id, name, start_time, end_time, app_id, note
+            FROM activities
+            ORDER BY start_time DESC, id DESC
+            LIMIT ? OFFSET ?;
+            "#,
+		)
+		.bind(limit)
+		.bind(offset)
+		.fetch_all(&self.pool)
+		.await?)
+	}
+
+	/// Sets the note of the session with `id`, or of the open session if not given, returning
+	/// whether there was such a session. An empty note removes it.
+	pub async fn set_note(&self, id: Option<i64>, note: &str) -> Result<bool> {
+		let note = (!note.is_empty()).then_some(note);
+		let result = match id {
+			Some(id) => {
+				sqlx::query("UPDATE activities SET note = ? WHERE id = ?;")
+					.bind(note)
+					.bind(id)
+					.execute(&self.pool)
+					.await?
+			}
+			None => {
+				sqlx::query("UPDATE activities SET note = ? WHERE end_time IS NULL;")
+					.bind(note)
+					.execute(&self.pool)
+					.await?
 			}
+		};
+		Ok(result.rows_affected() > 0)
+	}
+
+	/// Deletes the session with `id`, returning whether it existed. The open session can't be
+	/// deleted, the daemon still expects to end it.
+	pub async fn delete_event(&self, id: i64) -> Result<bool> {
+		let mut tx = self.pool.begin().await?;
+		let session: Option<(Option<i64>,)> =
+			sqlx::query_as("SELECT end_time FROM activities WHERE id = ?;")
+				.bind(id)
+				.fetch_optional(&mut *tx)
+				.await?;
+		match session {
+			None => return Ok(false),
+			Some((None,)) => anyhow::bail!("session {id} is still in progress"),
+			Some((Some(_),)) => {}
 		}
 
-		Ok(time_spent)
+		sqlx::query("DELETE FROM activities WHERE id = ?;")
+			.bind(id)
+			.execute(&mut *tx)
+			.await?;
+		tx.commit().await?;
+		self.invalidate_summaries();
+		Ok(true)
+	}
+
+	/// Moves the start and/or end of the session with `id`, returning whether it existed. The
+	/// session has to stay non-empty and can't be made to overlap any other session. The open
+	/// session's start can be moved, but it can't be given an end.
+	pub async fn update_event_times(
+		&self,
+		id: i64,
+		start_time: Option<DateTime<Local>>,
+		end_time: Option<DateTime<Local>>,
+	) -> Result<bool> {
+		let mut tx = self.pool.begin().await?;
+		let session: Option<(i64, Option<i64>)> =
+			sqlx::query_as("SELECT start_time, end_time FROM activities WHERE id = ?;")
+				.bind(id)
+				.fetch_optional(&mut *tx)
+				.await?;
+		let Some((old_start, old_end)) = session else {
+			return Ok(false);
+		};
+		if old_end.is_none() && end_time.is_some() {
+			anyhow::bail!("session {id} is still in progress, it can't be given an end");
+		}
+
+		let start = start_time.map_or(old_start, |x| x.timestamp());
+		let end = end_time.map(|x| x.timestamp()).or(old_end);
+		let now = Utc::now().timestamp();
+		if start >= end.unwrap_or(now) {
+			anyhow::bail!("session would end before it starts");
+		}
+
+		let (overlapping,): (i64,) = sqlx::query_as(
+			r#"
+            SELECT COUNT(*)
+            FROM activities
+            WHERE id != ?1 AND start_time < ?2 AND COALESCE(end_time, ?3) > ?4;
+            "#,
+		)
+		.bind(id)
+		.bind(end.unwrap_or(i64::MAX))
+		.bind(now)
+		.bind(start)
+		.fetch_one(&mut *tx)
+		.await?;
+		if overlapping > 0 {
+			anyhow::bail!("session would overlap {overlapping} other sessions");
+		}
+
+		sqlx::query(
+			r#"
+            UPDATE activities
+            SET start_time = ?, end_time = ?
+            WHERE id = ?;
+            "#,
+		)
+		.bind(start)
+		.bind(end)
+		.bind(id)
+		.execute(&mut *tx)
+		.await?;
+		tx.commit().await?;
+		self.invalidate_summaries();
+		Ok(true)
 	}
 
 	pub async fn close(&self) {
 		self.pool.close().await;
 	}
+
+	/// Records a session with the given bounds directly, for setting up tests.
+	#[cfg(test)]
+	pub async fn insert_session(&self, name: &str, start_time: i64, end_time: Option<i64>) -> i64 {
+		let result =
+			sqlx::query("INSERT INTO activities (name, start_time, end_time) VALUES (?, ?, ?);")
+				.bind(name)
+				.bind(start_time)
+				.bind(end_time)
+				.execute(&self.pool)
+				.await
+				.unwrap();
+		self.invalidate_summaries();
+		result.last_insert_rowid()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use sqlx::Connection;
+
+	use super::*;
+
+	fn options() -> DatabaseOptions {
+		DatabaseOptions {
+			summary_cache_size: 0,
+			max_connections: 1,
+			busy_timeout: std::time::Duration::from_secs(5),
+			min_duration: 0,
+			key: None,
+			read_only: false,
+		}
+	}
+
+	async fn memory_db(options: DatabaseOptions) -> Database {
+		Database::new(":memory:", options).await.unwrap()
+	}
+
+	fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+		Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+	}
+
+	/// Database file in the temp dir that's removed before and after the test.
+	struct TempDatabase(std::path::PathBuf);
+
+	impl TempDatabase {
+		fn new(name: &str) -> Self {
+			let path = std::env::temp_dir().join(format!(
+				"ktimetracker-test-{}-{name}.db3",
+				std::process::id()
+			));
+			let this = Self(path);
+			this.remove();
+			this
+		}
+
+		fn path(&self) -> &str {
+			self.0.to_str().unwrap()
+		}
+
+		fn remove(&self) {
+			for suffix in ["", "-wal", "-shm"] {
+				let mut path = self.0.clone().into_os_string();
+				path.push(suffix);
+				let _ = std::fs::remove_file(path);
+			}
+		}
+	}
+
+	impl Drop for TempDatabase {
+		fn drop(&mut self) {
+			self.remove();
+		}
+	}
+
+	fn utc(s: &str) -> DateTime<Utc> {
+		s.parse().unwrap()
+	}
+
+	#[tokio::test]
+	async fn daily_summary_keeps_every_hour_across_dst() {
+		let db = memory_db(options()).await;
+		let berlin = chrono_tz::Europe::Berlin;
+		for (start, end) in [
+			// spring forward on 2024-03-31, a 23 hour day
+			("2024-03-30T12:00:00Z", "2024-04-01T12:00:00Z"),
+			// fall back on 2024-10-27, a 25 hour day
+			("2024-10-26T12:00:00Z", "2024-10-28T12:00:00Z"),
+		] {
+			db.insert_session("a", utc(start).timestamp(), Some(utc(end).timestamp()))
+				.await;
+		}
+
+		let daily = db
+			.get_daily_summary(
+				Some(utc("2024-03-01T00:00:00Z").with_timezone(&Local)),
+				Some(utc("2024-11-01T00:00:00Z").with_timezone(&Local)),
+				berlin,
+			)
+			.await
+			.unwrap();
+		let hours: Vec<_> = daily
+			.iter()
+			.map(|(date, _, duration)| (date.to_string(), duration.num_hours()))
+			.collect();
+		let expected = [
+			("2024-03-30", 11),
+			("2024-03-31", 23),
+			("2024-04-01", 14),
+			("2024-10-26", 10),
+			("2024-10-27", 25),
+			("2024-10-28", 13),
+		];
+		assert_eq!(
+			hours,
+			expected.map(|(date, hours)| (date.to_string(), hours))
+		);
+		let total: i64 = hours.iter().map(|(_, hours)| hours).sum();
+		assert_eq!(total, 96);
+	}
+
+	#[tokio::test]
+	async fn cached_summary_is_invalidated_by_switching() {
+		let db = memory_db(DatabaseOptions {
+			summary_cache_size: 4,
+			..options()
+		})
+		.await;
+		let start = local(2024, 6, 5, 0, 0);
+		let end = local(2024, 6, 6, 0, 0);
+		db.insert_session(
+			"a",
+			local(2024, 6, 5, 9, 0).timestamp(),
+			Some(local(2024, 6, 5, 10, 0).timestamp()),
+		)
+		.await;
+		assert_eq!(
+			db.get_summary(Some(start), Some(end)).await.unwrap(),
+			vec![("a".to_string(), Duration::hours(1))]
+		);
+
+		// written behind the cache's back, so only the cached summary is returned
+		sqlx::query("INSERT INTO activities (name, start_time, end_time) VALUES ('b', ?, ?);")
+			.bind(local(2024, 6, 5, 12, 0).timestamp())
+			.bind(local(2024, 6, 5, 14, 0).timestamp())
+			.execute(&db.pool)
+			.await
+			.unwrap();
+		assert_eq!(
+			db.get_summary(Some(start), Some(end)).await.unwrap().len(),
+			1
+		);
+
+		db.switch_activity("c", None).await.unwrap();
+		assert_eq!(
+			db.get_summary(Some(start), Some(end)).await.unwrap(),
+			vec![
+				("b".to_string(), Duration::hours(2)),
+				("a".to_string(), Duration::hours(1)),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn switch_waits_out_a_locked_database() {
+		let file = TempDatabase::new("locked");
+		let db = Database::new(
+			file.path(),
+			DatabaseOptions {
+				max_connections: 2,
+				busy_timeout: std::time::Duration::from_millis(50),
+				..options()
+			},
+		)
+		.await
+		.unwrap();
+
+		let mut other = SqliteConnection::connect(&format!("sqlite://{}", file.path()))
+			.await
+			.unwrap();
+		sqlx::query("BEGIN IMMEDIATE;")
+			.execute(&mut other)
+			.await
+			.unwrap();
+		let started = Instant::now();
+		let release = tokio::spawn(async move {
+			tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+			sqlx::query("COMMIT;").execute(&mut other).await.unwrap();
+		});
+
+		db.switch_activity("a", None).await.unwrap();
+		assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+		release.await.unwrap();
+		assert_eq!(
+			db.get_current_activity().await.unwrap().as_deref(),
+			Some("a")
+		);
+		db.close().await;
+	}
+
+	#[tokio::test]
+	async fn summaries_read_while_a_session_is_written() {
+		let file = TempDatabase::new("wal");
+		let db = Database::new(
+			file.path(),
+			DatabaseOptions {
+				max_connections: 5,
+				// fail fast if a read ever has to wait on the writer
+				busy_timeout: std::time::Duration::from_millis(50),
+				..options()
+			},
+		)
+		.await
+		.unwrap();
+		db.switch_activity("a", None).await.unwrap();
+
+		let mut writer = SqliteConnection::connect(&format!("sqlite://{}", file.path()))
+			.await
+			.unwrap();
+		sqlx::query("BEGIN IMMEDIATE;")
+			.execute(&mut writer)
+			.await
+			.unwrap();
+		sqlx::query("INSERT INTO activities (name, start_time) VALUES ('b', 0);")
+			.execute(&mut writer)
+			.await
+			.unwrap();
+		let summary = db.get_summary(None, None).await.unwrap();
+		assert!(summary.iter().all(|(name, _)| name == "a"));
+		sqlx::query("COMMIT;").execute(&mut writer).await.unwrap();
+
+		let (switched, summary) =
+			tokio::join!(db.switch_activity("c", None), db.get_summary(None, None));
+		switched.unwrap();
+		summary.unwrap();
+		db.close().await;
+	}
+
+	#[tokio::test]
+	async fn sessions_shorter_than_min_duration_are_deleted() {
+		let db = memory_db(DatabaseOptions {
+			min_duration: 60,
+			..options()
+		})
+		.await;
+		let now = Utc::now().timestamp();
+
+		db.insert_session("short", now - 10, None).await;
+		db.end_current_activity().await.unwrap();
+		db.insert_session("long", now - 120, None).await;
+		db.end_current_activity().await.unwrap();
+		// still in progress, so not judged yet
+		db.insert_session("open", now - 10, None).await;
+
+		let names: Vec<_> = db
+			.get_events(50, 0)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|x| x.name)
+			.collect();
+		assert_eq!(names, ["open", "long"]);
+	}
+
+	#[tokio::test]
+	async fn hourly_summary_splits_sessions_at_hours() {
+		let db = memory_db(options()).await;
+		db.insert_session(
+			"a",
+			utc("2024-06-05T09:30:00Z").timestamp(),
+			Some(utc("2024-06-05T12:15:00Z").timestamp()),
+		)
+		.await;
+		db.insert_session(
+			"b",
+			utc("2024-06-05T10:00:00Z").timestamp(),
+			Some(utc("2024-06-05T10:20:00Z").timestamp()),
+		)
+		.await;
+
+		let hours = db.get_hourly_summary(None, None, None, Utc).await.unwrap();
+		let minutes: Vec<_> = hours.iter().map(|x| x.num_minutes()).collect();
+		let mut expected = [0; 24];
+		expected[9] = 30;
+		expected[10] = 80;
+		expected[11] = 60;
+		expected[12] = 15;
+		assert_eq!(minutes, expected);
+
+		let hours = db
+			.get_hourly_summary(None, None, Some("b"), Utc)
+			.await
+			.unwrap();
+		assert_eq!(hours[10], Duration::minutes(20));
+		assert_eq!(hours.iter().filter(|x| !x.is_zero()).count(), 1);
+	}
+
+	#[tokio::test]
+	async fn hourly_summary_wraps_around_midnight() {
+		let db = memory_db(options()).await;
+		db.insert_session(
+			"a",
+			utc("2024-06-05T22:45:00Z").timestamp(),
+			Some(utc("2024-06-06T01:10:00Z").timestamp()),
+		)
+		.await;
+
+		let hours = db.get_hourly_summary(None, None, None, Utc).await.unwrap();
+		assert_eq!(hours[22], Duration::minutes(15));
+		assert_eq!(hours[23], Duration::hours(1));
+		assert_eq!(hours[0], Duration::hours(1));
+		assert_eq!(hours[1], Duration::minutes(10));
+
+		// a zone with a half hour offset moves the boundaries
+		let hours = db
+			.get_hourly_summary(None, None, None, chrono_tz::Asia::Kolkata)
+			.await
+			.unwrap();
+		// 04:15 to 06:40 in Kolkata
+		assert_eq!(hours[4], Duration::minutes(45));
+		assert_eq!(hours[5], Duration::hours(1));
+		assert_eq!(hours[6], Duration::minutes(40));
+	}
+
+	#[tokio::test]
+	async fn edits_are_validated() {
+		let db = memory_db(options()).await;
+		let at = |h, m| local(2024, 6, 5, h, m);
+		let previous = db
+			.insert_session("a", at(9, 0).timestamp(), Some(at(10, 0).timestamp()))
+			.await;
+		let edited = db
+			.insert_session("b", at(11, 0).timestamp(), Some(at(12, 0).timestamp()))
+			.await;
+		db.insert_session("c", at(13, 0).timestamp(), Some(at(14, 0).timestamp()))
+			.await;
+		let open = db.insert_session("d", at(15, 0).timestamp(), None).await;
+
+		let overlap_previous = db.update_event_times(edited, Some(at(9, 30)), None).await;
+		assert!(
+			overlap_previous
+				.unwrap_err()
+				.to_string()
+				.contains("overlap")
+		);
+		let overlap_next = db.update_event_times(edited, None, Some(at(13, 30))).await;
+		assert!(overlap_next.unwrap_err().to_string().contains("overlap"));
+		let backwards = db.update_event_times(edited, Some(at(12, 30)), None).await;
+		assert!(
+			backwards
+				.unwrap_err()
+				.to_string()
+				.contains("end before it starts")
+		);
+		let ends_open = db.update_event_times(open, None, Some(at(16, 0))).await;
+		assert!(ends_open.unwrap_err().to_string().contains("in progress"));
+		assert!(
+			!db.update_event_times(1000, Some(at(8, 0)), None)
+				.await
+				.unwrap()
+		);
+
+		// touching the neighbours is fine
+		assert!(
+			db.update_event_times(edited, Some(at(10, 0)), Some(at(13, 0)))
+				.await
+				.unwrap()
+		);
+		assert!(
+			db.update_event_times(open, Some(at(14, 30)), None)
+				.await
+				.unwrap()
+		);
+		let events = db.get_events(50, 0).await.unwrap();
+		let times = |id| {
+			let event = events.iter().find(|x| x.id == id).unwrap();
+			(event.start_time, event.end_time)
+		};
+		assert_eq!(
+			times(edited),
+			(at(10, 0).timestamp(), Some(at(13, 0).timestamp()))
+		);
+		assert_eq!(times(open), (at(14, 30).timestamp(), None));
+		assert_eq!(
+			times(previous),
+			(at(9, 0).timestamp(), Some(at(10, 0).timestamp()))
+		);
+	}
+
+	#[tokio::test]
+	async fn export_streams_rows_in_order() {
+		let db = memory_db(options()).await;
+		sqlx::query(
+			r#"
+            WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 9999)
+            INSERT INTO activities (name, start_time, end_time)
+            SELECT 'a', 1000 + i * 10, 1005 + i * 10 FROM n;
+            "#,
+		)
+		.execute(&db.pool)
+		.await
+		.unwrap();
+
+		let mut rows = std::pin::pin!(db.export_rows(None, None));
+		let mut count = 0;
+		let mut last_start = 0;
+		while let Some(row) = rows.next().await {
+			let row = row.unwrap();
+			assert!(row.start_time > last_start);
+			last_start = row.start_time;
+			count += 1;
+		}
+		assert_eq!(count, 10_000);
+		assert_eq!(last_start, 1000 + 9999 * 10);
+	}
+
+	#[tokio::test]
+	async fn missing_database_file_is_created() {
+		let file = TempDatabase::new("create");
+		assert!(!file.0.exists());
+
+		let db = Database::new(file.path(), options()).await.unwrap();
+		assert!(file.0.exists());
+		let (tables,): (i64,) = sqlx::query_as(
+			"SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'activities';",
+		)
+		.fetch_one(&db.pool)
+		.await
+		.unwrap();
+		assert_eq!(tables, 1);
+		db.insert_session("a", 0, None).await;
+		db.close().await;
+
+		// the same file opened through a url
+		let db = Database::new(&format!("sqlite://{}", file.path()), options())
+			.await
+			.unwrap();
+		assert_eq!(
+			db.get_current_activity().await.unwrap().as_deref(),
+			Some("a")
+		);
+		db.close().await;
+	}
+
+	#[tokio::test]
+	async fn unbounded_summary_includes_earlier_days() {
+		let db = memory_db(options()).await;
+		let yesterday = (Utc::now() - Duration::days(1)).timestamp();
+		db.insert_session("a", yesterday, Some(yesterday + 600))
+			.await;
+		db.insert_session("b", yesterday + 600, Some(yesterday + 900))
+			.await;
+
+		let summary = db.get_summary(None, None).await.unwrap();
+		assert_eq!(
+			summary,
+			vec![
+				("a".to_string(), Duration::minutes(10)),
+				("b".to_string(), Duration::minutes(5)),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn old_schema_is_migrated() {
+		let file = TempDatabase::new("migrate");
+		{
+			let options = SqliteConnectOptions::new()
+				.filename(file.path())
+				.create_if_missing(true);
+			// the schema as created before migrations existed, which left user_version at 0
+			let mut old = SqliteConnection::connect_with(&options).await.unwrap();
+			sqlx::raw_sql(
+				r#"
+                CREATE TABLE activities (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    start_time INTEGER NOT NULL,
+                    end_time INTEGER
+                );
+                INSERT INTO activities (name, start_time, end_time) VALUES ('a', 100, 200);
+                "#,
+			)
+			.execute(&mut old)
+			.await
+			.unwrap();
+			let (version,): (i64,) = sqlx::query_as("PRAGMA user_version;")
+				.fetch_one(&mut old)
+				.await
+				.unwrap();
+			assert_eq!(version, 0);
+			old.close().await.unwrap();
+		}
+
+		let db = Database::new(file.path(), options()).await.unwrap();
+		let (version,): (i64,) = sqlx::query_as("PRAGMA user_version;")
+			.fetch_one(&db.pool)
+			.await
+			.unwrap();
+		assert_eq!(version as usize, MIGRATIONS.len());
+
+		let rows: Vec<Activity> = futures::TryStreamExt::try_collect(db.export_rows(None, None))
+			.await
+			.unwrap();
+		assert_eq!(rows.len(), 1);
+		assert_eq!(
+			(rows[0].name.as_str(), rows[0].start_time, rows[0].end_time),
+			("a", 100, Some(200))
+		);
+		assert_eq!(
+			(rows[0].app_id.as_deref(), rows[0].note.as_deref()),
+			(None, None)
+		);
+
+		// the tables added by later migrations are usable
+		db.switch_activity("b", None).await.unwrap();
+		db.close().await;
+	}
+
+	#[tokio::test]
+	async fn summary_matches_clamped_sessions() {
+		let db = memory_db(options()).await;
+		let sessions = [
+			("a", 0, Some(1500)),    // starts before the window
+			("b", 1500, Some(2500)), // inside
+			("a", 2500, Some(5000)), // ends after the window
+			("c", 500, Some(4500)),  // covers the whole window
+			("d", 100, Some(900)),   // before the window
+			("b", 3900, None),       // open at the window end
+		];
+		for (name, start, end) in sessions {
+			db.insert_session(name, start, end).await;
+		}
+
+		let (start, end) = (1000, 4000);
+		let mut expected: Vec<(String, Duration)> = Vec::new();
+		for (name, session_start, session_end) in sessions {
+			let seconds = session_end.unwrap_or(end).min(end) - session_start.max(start);
+			if seconds <= 0 {
+				continue;
+			}
+			match expected.iter_mut().find(|(x, _)| x == name) {
+				Some((_, total)) => *total += Duration::seconds(seconds),
+				None => expected.push((name.to_string(), Duration::seconds(seconds))),
+			}
+		}
+		expected.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+		let summary = db
+			.get_summary(
+				Some(
+					DateTime::from_timestamp(start, 0)
+						.unwrap()
+						.with_timezone(&Local),
+				),
+				Some(
+					DateTime::from_timestamp(end, 0)
+						.unwrap()
+						.with_timezone(&Local),
+				),
+			)
+			.await
+			.unwrap();
+		assert_eq!(summary, expected);
+		assert_eq!(summary[0], ("c".to_string(), Duration::seconds(3000)));
+	}
+
+	#[tokio::test]
+	async fn summary_is_sorted_by_duration() {
+		let db = memory_db(options()).await;
+		db.insert_session("a", 0, Some(60)).await;
+		db.insert_session("c", 60, Some(360)).await;
+		db.insert_session("b", 360, Some(480)).await;
+		db.insert_session("a", 480, Some(540)).await;
+
+		let summary = db
+			.get_summary(
+				Some(
+					DateTime::from_timestamp(0, 0)
+						.unwrap()
+						.with_timezone(&Local),
+				),
+				Some(
+					DateTime::from_timestamp(1000, 0)
+						.unwrap()
+						.with_timezone(&Local),
+				),
+			)
+			.await
+			.unwrap();
+		// equal durations are ordered by name
+		assert_eq!(
+			summary,
+			vec![
+				("c".to_string(), Duration::minutes(5)),
+				("a".to_string(), Duration::minutes(2)),
+				("b".to_string(), Duration::minutes(2)),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn sessions_never_overlap_after_the_clock_jumps_back() {
+		let db = memory_db(options()).await;
+		db.switch_activity("a", None).await.unwrap();
+		// as if the wall clock was moved back an hour since "a" started
+		let (start_time,): (i64,) = sqlx::query_as("SELECT start_time FROM activities;")
+			.fetch_one(&db.pool)
+			.await
+			.unwrap();
+		let start_time = start_time + 3600;
+		sqlx::query("UPDATE activities SET start_time = ?;")
+			.bind(start_time)
+			.execute(&db.pool)
+			.await
+			.unwrap();
+		db.set_session_started(Some((start_time, Instant::now())));
+
+		db.switch_activity("b", None).await.unwrap();
+		db.end_current_activity().await.unwrap();
+		let events = db.get_events(10, 0).await.unwrap();
+		let (b, a) = (&events[0], &events[1]);
+		assert_eq!((a.name.as_str(), b.name.as_str()), ("a", "b"));
+		assert!(a.end_time.unwrap() >= start_time);
+		assert_eq!(b.start_time, a.end_time.unwrap());
+		assert!(b.end_time.unwrap() >= b.start_time);
+	}
+
+	/// Runs `f` while another connection holds the write lock for 300ms.
+	async fn while_locked<T>(path: &str, f: impl Future<Output = Result<T>>) -> T {
+		let mut other = SqliteConnection::connect(&format!("sqlite://{path}"))
+			.await
+			.unwrap();
+		sqlx::query("BEGIN IMMEDIATE;")
+			.execute(&mut other)
+			.await
+			.unwrap();
+		let started = Instant::now();
+		let release = async {
+			tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+			sqlx::query("COMMIT;").execute(&mut other).await.unwrap();
+		};
+		let (result, ()) = tokio::join!(f, release);
+		assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+		result.unwrap()
+	}
+
+	#[tokio::test]
+	async fn idle_and_app_changes_wait_out_a_locked_database() {
+		let file = TempDatabase::new("locked-events");
+		let db = Database::new(
+			file.path(),
+			DatabaseOptions {
+				max_connections: 2,
+				busy_timeout: std::time::Duration::from_millis(50),
+				..options()
+			},
+		)
+		.await
+		.unwrap();
+		db.switch_activity("a", None).await.unwrap();
+
+		while_locked(file.path(), db.start_idle_period(Utc::now())).await;
+		while_locked(file.path(), db.end_idle_period()).await;
+		while_locked(file.path(), db.switch_app(Some("firefox"))).await;
+
+		let events = db.get_events(10, 0).await.unwrap();
+		assert_eq!(events[0].app_id.as_deref(), Some("firefox"));
+		db.close().await;
+	}
 }
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use log::warn;
+use tokio::sync::watch;
+use zbus::{Connection, interface, object_server::SignalEmitter};
+
+use crate::{daemon::TrackingState, db::Database, kactivities::KActivitiesConnection};
+
+/// Owned by the daemon for every object it serves on the session bus.
+pub const BUS_NAME: &str = "dev.r58playz.ktimetracker";
+const OBJECT_PATH: &str = "/dev/r58playz/ktimetracker";
+
+struct Tracker {
+	db: Arc<Database>,
+	kactivities_conn: KActivitiesConnection,
+}
+
+impl Tracker {
+	/// Name of the tracked activity, falling back to its UUID, and how long it's been tracked.
+	/// Both are empty if nothing is tracked.
+	async fn current(&self) -> Result<(String, i64)> {
+		let Some(activity) = self.db.get_current_activity().await? else {
+			return Ok((String::new(), 0));
+		};
+		let name = match self
+			.kactivities_conn
+			.query_activity_info(activity.clone())
+			.await
+		{
+			Ok(info) if !info.name.is_empty() => info.name,
+			_ => activity,
+		};
+		let elapsed = self
+			.db
+			.get_current_activity_elapsed_time()
+			.await?
+			.map_or(0, |x| x.num_seconds());
+		Ok((name, elapsed))
+	}
+}
+
+#[interface(name = "dev.r58playz.ktimetracker1")]
+impl Tracker {
+	async fn current_activity(&self) -> zbus::fdo::Result<(String, i64)> {
+		self.current()
+			.await
+			.map_err(|e| zbus::fdo::Error::Failed(format!("{e:#}")))
+	}
+
+	#[zbus(signal)]
+	async fn current_activity_changed(
+		emitter: &SignalEmitter<'_>,
+		name: &str,
+		elapsed_seconds: i64,
+	) -> zbus::Result<()>;
+}
+
+/// Serves the tracked activity on the session bus for desktop integrations like plasmoids,
+/// alongside the unix socket.
+pub struct DBusService {
+	conn: Connection,
+}
+
+impl DBusService {
+	/// Serves the tracker on `conn` and takes [`BUS_NAME`] for it, so other objects served on the
+	/// same connection are reachable under that name too.
+	pub async fn new(
+		conn: &Connection,
+		db: Arc<Database>,
+		kactivities_conn: KActivitiesConnection,
+	) -> Result<Self> {
+		conn.object_server()
+			.at(
+				OBJECT_PATH,
+				Tracker {
+					db,
+					kactivities_conn,
+				},
+			)
+			.await
+			.context("failed to serve tracker interface")?;
+		conn.request_name(BUS_NAME)
+			.await
+			.with_context(|| format!("failed to register {BUS_NAME} on the session bus"))?;
+
+		Ok(Self { conn: conn.clone() })
+	}
+
+	/// Emits `CurrentActivityChanged` whenever the tracked activity changes, until the daemon
+	/// stops publishing its state.
+	pub async fn daemon(self, mut state_rx: watch::Receiver<TrackingState>) -> Result<()> {
+		let iface = self
+			.conn
+			.object_server()
+			.interface::<_, Tracker>(OBJECT_PATH)
+			.await
+			.context("failed to get tracker interface")?;
+
+		let mut activity = state_rx.borrow_and_update().activity.clone();
+		while state_rx.changed().await.is_ok() {
+			let new_activity = state_rx.borrow_and_update().activity.clone();
+			if new_activity == activity {
+				continue;
+			}
+			activity = new_activity;
+
+			let current = iface.get().await.current().await;
+			let result = match current {
+				Ok((name, elapsed)) => {
+					Tracker::current_activity_changed(iface.signal_emitter(), &name, elapsed)
+						.await
+						.map_err(anyhow::Error::from)
+				}
+				Err(e) => Err(e),
+			};
+			if let Err(e) = result {
+				warn!("failed to emit activity change on d-bus: {e:#}");
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+
+/// Starts an iCalendar (RFC 5545) calendar, to be followed by events and [`footer`].
+pub fn header() -> String {
+	let mut out = String::new();
+	push_line(&mut out, "BEGIN:VCALENDAR");
+	push_line(&mut out, "VERSION:2.0");
+	push_line(&mut out, "PRODID:-//r58Playz//ktimetracker//EN");
+	out
+}
+
+pub fn footer() -> String {
+	let mut out = String::new();
+	push_line(&mut out, "END:VCALENDAR");
+	out
+}
+
+/// Formats one session as a `VEVENT`. Open sessions should be passed with `end_time` set to now.
+pub fn event(id: i64, name: &str, start_time: i64, end_time: i64, stamp: DateTime<Utc>) -> String {
+	let mut out = String::new();
+	push_line(&mut out, "BEGIN:VEVENT");
+	push_line(
+		&mut out,
+		&format!("UID:session-{id}@ktimetracker.r58playz.dev"),
+	);
+	push_line(&mut out, &format!("DTSTAMP:{}", format_datetime(stamp)));
+	push_line(
+		&mut out,
+		&format!("DTSTART:{}", format_timestamp(start_time)),
+	);
+	push_line(&mut out, &format!("DTEND:{}", format_timestamp(end_time)));
+	push_line(&mut out, &format!("SUMMARY:{}", escape_text(name)));
+	push_line(&mut out, "END:VEVENT");
+	out
+}
+
+/// Appends a content line, folding it so no physical line is longer than 75 octets.
+fn push_line(out: &mut String, line: &str) {
+	let mut len = 0;
+	for c in line.chars() {
+		if len + c.len_utf8() > 75 {
+			out.push_str("\r\n ");
+			len = 1;
+		}
+		out.push(c);
+		len += c.len_utf8();
+	}
+	out.push_str("\r\n");
+}
+
+fn format_datetime(datetime: DateTime<Utc>) -> String {
+	datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+	format_datetime(DateTime::from_timestamp(timestamp, 0).unwrap_or_default())
+}
+
+fn escape_text(text: &str) -> String {
+	text.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace('\r', "")
+		.replace('\n', "\\n")
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use log::warn;
+use tokio::sync::{mpsc::UnboundedSender, watch};
+
+use crate::{daemon::DaemonEvent, screensaver::ScreenSaverIdle, wayland::WaylandConnection};
+
+/// Settings for idle detection that only some backends look at.
+#[derive(Debug, Clone, Default)]
+pub struct IdleOptions {
+	/// Wayland seat to watch instead of all of them
+	pub seat: Option<String>,
+}
+
+/// A backend for idle detection. Its only contact with the daemon is sending
+/// [`DaemonEvent::IdleStatusChanged`] whenever the user goes idle or comes back.
+pub trait IdleSource {
+	/// Name used in logs.
+	const NAME: &'static str;
+
+	/// Detects idle until the backend fails for good. The idle timeout in milliseconds is the
+	/// current value of `idle_timeout`, and has to be re-armed whenever it changes.
+	fn run(
+		sender: UnboundedSender<DaemonEvent>,
+		idle_timeout: watch::Receiver<u32>,
+		options: IdleOptions,
+	) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Picks wayland or the screensaver interface depending on the session, falling back to the other
+/// if the preferred one fails.
+pub async fn run(
+	sender: UnboundedSender<DaemonEvent>,
+	idle_timeout: watch::Receiver<u32>,
+	options: IdleOptions,
+) -> Result<()> {
+	if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+		with_fallback::<WaylandConnection, ScreenSaverIdle>(sender, idle_timeout, options).await
+	} else {
+		with_fallback::<ScreenSaverIdle, WaylandConnection>(sender, idle_timeout, options).await
+	}
+}
+
+/// Runs `A`, and `B` once `A` gives up. Backends fail right away if they can't connect at all, so
+/// the fallback takes over at startup without waiting out `A`'s reconnect attempts.
+async fn with_fallback<A: IdleSource, B: IdleSource>(
+	sender: UnboundedSender<DaemonEvent>,
+	idle_timeout: watch::Receiver<u32>,
+	options: IdleOptions,
+) -> Result<()> {
+	let Err(e) = A::run(sender.clone(), idle_timeout.clone(), options.clone()).await else {
+		return Ok(());
+	};
+	warn!(
+		"{} idle detection failed, falling back to {}: {e:#}",
+		A::NAME,
+		B::NAME
+	);
+	B::run(sender, idle_timeout, options).await
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Mutex;
+
+	use super::*;
+
+	struct FailingSource;
+
+	impl IdleSource for FailingSource {
+		const NAME: &'static str = "failing";
+
+		async fn run(
+			_sender: UnboundedSender<DaemonEvent>,
+			_idle_timeout: watch::Receiver<u32>,
+			_options: IdleOptions,
+		) -> Result<()> {
+			anyhow::bail!("no compositor")
+		}
+	}
+
+	/// every timeout `ScriptedSource` was armed with
+	static ARMED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+	/// Goes idle, then comes back once it's re-armed with a new timeout.
+	struct ScriptedSource;
+
+	impl IdleSource for ScriptedSource {
+		const NAME: &'static str = "scripted";
+
+		async fn run(
+			sender: UnboundedSender<DaemonEvent>,
+			mut idle_timeout: watch::Receiver<u32>,
+			_options: IdleOptions,
+		) -> Result<()> {
+			ARMED
+				.lock()
+				.unwrap()
+				.push(*idle_timeout.borrow_and_update());
+			let _ = sender.send(DaemonEvent::IdleStatusChanged { idle: true });
+			idle_timeout.changed().await?;
+			ARMED
+				.lock()
+				.unwrap()
+				.push(*idle_timeout.borrow_and_update());
+			let _ = sender.send(DaemonEvent::IdleStatusChanged { idle: false });
+			Ok(())
+		}
+	}
+
+	#[tokio::test]
+	async fn fallback_takes_over_and_is_re_armed() {
+		let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+		let (idle_timeout_tx, idle_timeout) = watch::channel(1000);
+		let task = tokio::spawn(with_fallback::<FailingSource, ScriptedSource>(
+			sender,
+			idle_timeout,
+			IdleOptions::default(),
+		));
+
+		assert!(matches!(
+			events.recv().await,
+			Some(DaemonEvent::IdleStatusChanged { idle: true })
+		));
+		idle_timeout_tx.send(2000).unwrap();
+		assert!(matches!(
+			events.recv().await,
+			Some(DaemonEvent::IdleStatusChanged { idle: false })
+		));
+		task.await.unwrap().unwrap();
+		assert_eq!(*ARMED.lock().unwrap(), [1000, 2000]);
+	}
+}
@@ -1,13 +1,20 @@
-use anyhow::{Context, Result};
-use futures::StreamExt;
-use log::error;
+use anyhow::{Context, Result, anyhow};
+use futures::{StreamExt, future::BoxFuture};
+use log::{error, info, warn};
+use std::{
+	collections::HashMap,
+	pin::pin,
+	time::{Duration, Instant},
+};
 use tokio::{
 	select,
 	sync::{mpsc, oneshot},
 };
 use zbus::{Connection, proxy};
 
-use crate::daemon::DaemonEvent;
+use crate::daemon::{DaemonEvent, Source};
+
+const ACTIVITY_INFO_TTL: Duration = Duration::from_secs(300);
 
 #[proxy(
 	default_service = "org.kde.ActivityManager",
@@ -19,21 +26,46 @@ trait KdeActivityManager {
 	fn current_activity_changed(&self, activity: String) -> zbus::Result<()>;
 
 	fn current_activity(&self) -> zbus::Result<String>;
+	fn list_activities(&self) -> zbus::Result<Vec<String>>;
 
 	fn activity_name(&self, activity: &str) -> zbus::Result<String>;
 	fn activity_description(&self, activity: &str) -> zbus::Result<String>;
+	fn activity_icon(&self, activity: &str) -> zbus::Result<String>;
 }
 
 enum KActivitiesMessage {
 	CurrentActivity(oneshot::Sender<Result<String>>),
+	ListActivities(oneshot::Sender<Result<Vec<String>>>),
 	ActivityInfo(String, oneshot::Sender<Result<ActivityInfo>>),
 	ActivityChanged(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct ActivityInfo {
 	pub name: String,
 	pub description: String,
+	/// icon name, empty if the activity has none
+	pub icon: String,
+}
+
+/// Looks up KDE activities for clients. Implemented by [`KActivitiesConnection`], and kept separate
+/// so client requests can be served without a desktop session.
+pub trait ActivityResolver: Send + Sync {
+	fn query_activities(&self) -> BoxFuture<'_, Result<Vec<String>>>;
+	fn query_activity_info(&self, activity: String) -> BoxFuture<'_, Result<ActivityInfo>>;
+}
+
+/// Resolver for when the session bus isn't reachable, activities are only known by their UUID.
+pub struct NoActivityManager;
+
+impl ActivityResolver for NoActivityManager {
+	fn query_activities(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+		Box::pin(async { Ok(Vec::new()) })
+	}
+
+	fn query_activity_info(&self, _activity: String) -> BoxFuture<'_, Result<ActivityInfo>> {
+		Box::pin(async { Ok(ActivityInfo::default()) })
+	}
 }
 
 pub struct KActivitiesConnection {
@@ -48,20 +80,44 @@ impl Clone for KActivitiesConnection {
 	}
 }
 
+impl ActivityResolver for KActivitiesConnection {
+	fn query_activities(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+		Box::pin(KActivitiesConnection::query_activities(self))
+	}
+
+	fn query_activity_info(&self, activity: String) -> BoxFuture<'_, Result<ActivityInfo>> {
+		Box::pin(KActivitiesConnection::query_activity_info(self, activity))
+	}
+}
+
 impl KActivitiesConnection {
-	pub async fn new(daemon: mpsc::UnboundedSender<DaemonEvent>) -> Result<Self> {
+	/// `default_activity` is reported as the current activity while the activity manager isn't
+	/// running, e.g. outside of KDE, so tracking still works off idle and sleep alone.
+	pub async fn new(
+		daemon: mpsc::UnboundedSender<DaemonEvent>,
+		default_activity: String,
+	) -> Result<Self> {
 		let conn = Connection::session()
 			.await
 			.context("failed to connect to d-bus session bus")?;
+		Ok(Self::with_connection(conn, daemon, default_activity))
+	}
 
+	/// Like [`Self::new`], but starts out on a session bus connection shared with the rest of the
+	/// daemon. It's only replaced if it drops.
+	pub fn with_connection(
+		conn: Connection,
+		daemon: mpsc::UnboundedSender<DaemonEvent>,
+		default_activity: String,
+	) -> Self {
 		let (actor, actor_rx) = mpsc::unbounded_channel();
 		tokio::spawn(async move {
-			if let Err(e) = Self::daemon(conn, actor_rx, daemon).await {
+			if let Err(e) = Self::daemon(Some(conn), actor_rx, daemon, default_activity).await {
 				error!("kde activities connection failed: {e}");
 			}
 		});
 
-		Ok(Self { actor })
+		Self { actor }
 	}
 
 	pub async fn query_current_activity(&self) -> Result<String> {
@@ -76,6 +132,18 @@ impl KActivitiesConnection {
 			.flatten()
 	}
 
+	pub async fn query_activities(&self) -> Result<Vec<String>> {
+		let (tx, rx) = oneshot::channel();
+
+		self.actor
+			.send(KActivitiesMessage::ListActivities(tx))
+			.context("failed to send request to actor")?;
+
+		rx.await
+			.context("failed to get result from actor")
+			.flatten()
+	}
+
 	pub async fn query_activity_info(&self, activity: String) -> Result<ActivityInfo> {
 		let (tx, rx) = oneshot::channel();
 
@@ -88,30 +156,153 @@ impl KActivitiesConnection {
 			.flatten()
 	}
 
+	/// Serves requests, rebuilding the session bus connection whenever it drops. Requests that
+	/// arrive while reconnecting are answered with an error instead of waiting for the connection.
 	async fn daemon(
-		conn: Connection,
+		mut conn: Option<Connection>,
 		mut rx: mpsc::UnboundedReceiver<KActivitiesMessage>,
 		daemon: mpsc::UnboundedSender<DaemonEvent>,
+		default_activity: String,
 	) -> Result<()> {
-		let proxy = KdeActivityManagerProxy::new(&conn)
+		let mut failures = 0;
+		let mut cache = HashMap::new();
+		loop {
+			let started = Instant::now();
+			cache.clear();
+			let result = async {
+				let conn = match conn.take() {
+					Some(conn) => conn,
+					None => Connection::session()
+						.await
+						.context("failed to connect to d-bus session bus")?,
+				};
+				Self::serve(&conn, &mut rx, &daemon, &mut cache, &default_activity).await
+			}
+			.await;
+			let Err(e) = result else {
+				break Ok(());
+			};
+
+			if started.elapsed() > Duration::from_secs(60) {
+				failures = 0;
+			}
+			let _ = daemon.send(DaemonEvent::SourceStatus {
+				source: Source::KActivities,
+				connected: false,
+			});
+			failures += 1;
+			let backoff = Duration::from_secs(1 << (failures - 1).min(5));
+			warn!("kde activities connection lost, reconnecting in {backoff:?}: {e:#}");
+
+			let mut sleep = pin!(tokio::time::sleep(backoff));
+			loop {
+				select! {
+					_ = &mut sleep => break,
+					x = rx.recv() => match x {
+						Some(KActivitiesMessage::CurrentActivity(tx)) => {
+							let _ = tx.send(Err(anyhow!("kde activity manager is unavailable")));
+						}
+						Some(KActivitiesMessage::ListActivities(tx)) => {
+							let _ = tx.send(Err(anyhow!("kde activity manager is unavailable")));
+						}
+						Some(KActivitiesMessage::ActivityInfo(_, tx)) => {
+							let _ = tx.send(Err(anyhow!("kde activity manager is unavailable")));
+						}
+						Some(KActivitiesMessage::ActivityChanged(_)) => {}
+						None => return Ok(()),
+					}
+				}
+			}
+		}
+	}
+
+	/// Returns `Ok` once the daemon has gone away, or an error if the connection needs rebuilding.
+	///
+	/// Activity info lookups are cached for `ACTIVITY_INFO_TTL`, and the whole cache is dropped
+	/// whenever the current activity changes so renamed activities are picked up quickly.
+	///
+	/// While the activity manager has no owner, requests are answered with `default_activity`,
+	/// no activities and empty activity info instead of failing.
+	async fn serve(
+		conn: &Connection,
+		rx: &mut mpsc::UnboundedReceiver<KActivitiesMessage>,
+		daemon: &mpsc::UnboundedSender<DaemonEvent>,
+		cache: &mut HashMap<String, (Instant, ActivityInfo)>,
+		default_activity: &str,
+	) -> Result<()> {
+		let proxy = KdeActivityManagerProxy::new(conn)
 			.await
 			.context("failed to bind to kde activity manager")?;
 		let mut activity_signal = proxy
 			.receive_current_activity_changed()
 			.await
 			.context("failed to bind to current activity changed signal")?;
+		let mut owner_changed = proxy
+			.inner()
+			.receive_owner_changed()
+			.await
+			.context("failed to watch kde activity manager owner")?;
+		let mut running = proxy.current_activity().await.is_ok();
+		if !running {
+			warn!("kde activity manager isn't running, tracking {default_activity} until it is");
+		}
+		let _ = daemon.send(DaemonEvent::SourceStatus {
+			source: Source::KActivities,
+			connected: running,
+		});
 
 		loop {
 			match select! {
 				x = activity_signal.next() => {
-					x.map(|x| anyhow::Ok(KActivitiesMessage::ActivityChanged(x.args().context("failed to parse signal")?.activity))).transpose()?
+					let x = x.context("current activity changed signal stream ended")?;
+					Some(KActivitiesMessage::ActivityChanged(x.args().context("failed to parse signal")?.activity))
+				},
+				x = owner_changed.next() => {
+					match x.context("activity manager owner stream ended")? {
+						Some(owner) => {
+							// the activity manager restarted, so the activity may have changed meanwhile
+							info!("kde activity manager is now owned by {owner}");
+							let activity = proxy
+								.current_activity()
+								.await
+								.context("failed to get current activity")?;
+							running = true;
+							let _ = daemon.send(DaemonEvent::SourceStatus {
+								source: Source::KActivities,
+								connected: true,
+							});
+							Some(KActivitiesMessage::ActivityChanged(activity))
+						}
+						None => {
+							warn!("kde activity manager went away, tracking {default_activity} until it's back");
+							running = false;
+							let _ = daemon.send(DaemonEvent::SourceStatus {
+								source: Source::KActivities,
+								connected: false,
+							});
+							Some(KActivitiesMessage::ActivityChanged(default_activity.to_string()))
+						}
+					}
 				},
 				x = rx.recv() => x
 			} {
 				Some(KActivitiesMessage::ActivityChanged(activity)) => {
-					daemon
+					cache.clear();
+					if daemon
 						.send(DaemonEvent::KdeActivityChanged { activity })
-						.context("failed to send activity changed to daemon")?;
+						.is_err()
+					{
+						break Ok(());
+					}
+				}
+				Some(KActivitiesMessage::CurrentActivity(tx)) if !running => {
+					let _ = tx.send(Ok(default_activity.to_string()));
+				}
+				Some(KActivitiesMessage::ListActivities(tx)) if !running => {
+					let _ = tx.send(Ok(Vec::new()));
+				}
+				Some(KActivitiesMessage::ActivityInfo(_, tx)) if !running => {
+					let _ = tx.send(Ok(ActivityInfo::default()));
 				}
 				Some(KActivitiesMessage::CurrentActivity(tx)) => {
 					let _ = tx.send(
@@ -121,7 +312,22 @@ impl KActivitiesConnection {
 							.context("failed to get current activity"),
 					);
 				}
+				Some(KActivitiesMessage::ListActivities(tx)) => {
+					let _ = tx.send(
+						proxy
+							.list_activities()
+							.await
+							.context("failed to list activities"),
+					);
+				}
 				Some(KActivitiesMessage::ActivityInfo(activity, tx)) => {
+					if let Some((fetched, info)) = cache.get(&activity)
+						&& fetched.elapsed() < ACTIVITY_INFO_TTL
+					{
+						let _ = tx.send(Ok(info.clone()));
+						continue;
+					}
+
 					let ret = async {
 						let name = proxy
 							.activity_name(&activity)
@@ -131,11 +337,19 @@ impl KActivitiesConnection {
 							.activity_description(&activity)
 							.await
 							.context("failed to get activity description")?;
+						let icon = proxy.activity_icon(&activity).await.unwrap_or_default();
 
-						anyhow::Ok(ActivityInfo { name, description })
+						anyhow::Ok(ActivityInfo {
+							name,
+							description,
+							icon,
+						})
 					}
 					.await;
 
+					if let Ok(info) = &ret {
+						cache.insert(activity, (Instant::now(), info.clone()));
+					}
 					let _ = tx.send(ret);
 				}
 				None => break Ok(()),
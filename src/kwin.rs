@@ -0,0 +1,162 @@
+use std::{
+	io::Write,
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use tokio::sync::mpsc;
+use zbus::{Connection, interface, proxy};
+
+use crate::daemon::DaemonEvent;
+
+const OBJECT_PATH: &str = "/dev/r58playz/ktimetracker/WindowTracker";
+const PLUGIN_NAME: &str = "ktimetracker-window-tracker";
+
+/// Reports the focused window back to [`WindowTracker`] whenever it changes. KWin doesn't expose
+/// the active window over D-Bus directly, so this is loaded as a KWin script instead.
+const SCRIPT: &str = r#"
+function report(window) {
+    var appId = window ? (window.desktopFileName || window.resourceClass || "") : "";
+    callDBus("dev.r58playz.ktimetracker", "/dev/r58playz/ktimetracker/WindowTracker",
+        "dev.r58playz.ktimetracker.WindowTracker", "WindowActivated", appId);
+}
+workspace.windowActivated.connect(report);
+report(workspace.activeWindow);
+"#;
+
+#[proxy(
+	default_service = "org.kde.KWin",
+	default_path = "/Scripting",
+	interface = "org.kde.kwin.Scripting"
+)]
+trait KWinScripting {
+	fn load_script(&self, file_path: &str, plugin_name: &str) -> zbus::Result<i32>;
+	fn unload_script(&self, plugin_name: &str) -> zbus::Result<bool>;
+	fn start(&self) -> zbus::Result<()>;
+}
+
+struct WindowTracker {
+	daemon: mpsc::UnboundedSender<DaemonEvent>,
+}
+
+#[interface(name = "dev.r58playz.ktimetracker.WindowTracker")]
+impl WindowTracker {
+	fn window_activated(&self, app_id: String) {
+		let app_id = (!app_id.is_empty()).then_some(app_id);
+		let _ = self.daemon.send(DaemonEvent::WindowChanged { app_id });
+	}
+}
+
+/// Tracks the focused window through a KWin script, which only works under KWin.
+pub struct KWinConnection {
+	conn: Connection,
+	script_path: PathBuf,
+}
+
+impl KWinConnection {
+	/// `conn` has to own [`crate::dbus::BUS_NAME`], which the script calls back to.
+	pub async fn new(
+		conn: &Connection,
+		daemon: mpsc::UnboundedSender<DaemonEvent>,
+	) -> Result<Self> {
+		conn.object_server()
+			.at(OBJECT_PATH, WindowTracker { daemon })
+			.await
+			.context("failed to export window tracker on d-bus session bus")?;
+		let conn = conn.clone();
+
+		let script_path = Self::script_path()?;
+		Self::write_script(&script_path)
+			.with_context(|| format!("failed to write kwin script to {}", script_path.display()))?;
+
+		let scripting = KWinScriptingProxy::new(&conn)
+			.await
+			.context("failed to bind to kwin scripting")?;
+		// a previous daemon that didn't exit cleanly may have left its script loaded
+		if let Err(e) = scripting.unload_script(PLUGIN_NAME).await {
+			warn!("failed to unload a leftover kwin script: {e}");
+		}
+		scripting
+			.load_script(
+				script_path
+					.to_str()
+					.context("kwin script path is not valid UTF-8")?,
+				PLUGIN_NAME,
+			)
+			.await
+			.context("failed to load kwin script")?;
+		scripting
+			.start()
+			.await
+			.context("failed to start kwin script")?;
+		info!("tracking windows through kwin");
+
+		Ok(Self { conn, script_path })
+	}
+
+	/// KWin runs whatever is at this path, so it has to be somewhere only we can write to. Unlike
+	/// the temp dir, the runtime dir is private to the user.
+	fn script_path() -> Result<PathBuf> {
+		let dir = std::env::var_os("XDG_RUNTIME_DIR").context(
+			"XDG_RUNTIME_DIR isn't set, so there's nowhere private to put the kwin script",
+		)?;
+		Ok(PathBuf::from(dir).join("ktimetracker-kwin.js"))
+	}
+
+	/// Writes the script to a freshly created file, never through a file or symlink that's already
+	/// at `path`.
+	fn write_script(path: &Path) -> std::io::Result<()> {
+		match std::fs::remove_file(path) {
+			Ok(()) => {}
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+			Err(e) => return Err(e),
+		}
+		std::fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(path)?
+			.write_all(SCRIPT.as_bytes())
+	}
+
+	/// Unloads the script so KWin stops calling into a daemon that's going away.
+	pub async fn close(self) {
+		let res = async {
+			KWinScriptingProxy::new(&self.conn)
+				.await?
+				.unload_script(PLUGIN_NAME)
+				.await
+		}
+		.await;
+		if let Err(e) = res {
+			warn!("failed to unload kwin script: {e}");
+		}
+		let _ = std::fs::remove_file(&self.script_path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn script_replaces_a_planted_symlink() {
+		let dir =
+			std::env::temp_dir().join(format!("ktimetracker-test-{}-kwin", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir(&dir).unwrap();
+		let target = dir.join("target");
+		let path = dir.join("ktimetracker-kwin.js");
+		std::fs::write(&target, "untouched").unwrap();
+		std::os::unix::fs::symlink(&target, &path).unwrap();
+
+		KWinConnection::write_script(&path).unwrap();
+		assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+		assert!(!std::fs::symlink_metadata(&path).unwrap().is_symlink());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), SCRIPT);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
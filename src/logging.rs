@@ -0,0 +1,76 @@
+use std::{
+	fs::{File, OpenOptions},
+	io::{self, Write},
+	path::{Path, PathBuf},
+};
+
+use env_logger::Target;
+use log::{LevelFilter, warn};
+
+/// Size a log file may grow to before it's moved to `<file>.1`, replacing the previous one.
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Sets up logging for this crate at `level` to stderr, or to `file` if given. `RUST_LOG` still
+/// overrides the level. A log file that can't be opened falls back to stderr with a warning.
+pub fn init(level: LevelFilter, file: Option<&Path>) {
+	let mut builder = env_logger::builder();
+	builder
+		.filter_level(LevelFilter::Off)
+		.filter_module("ktimetracker", level)
+		.parse_default_env();
+
+	let Some(path) = file else {
+		builder.init();
+		return;
+	};
+	match RotatingFile::open(path.to_path_buf()) {
+		Ok(file) => {
+			builder.target(Target::Pipe(Box::new(file))).init();
+		}
+		Err(e) => {
+			builder.init();
+			warn!(
+				"failed to open log file {}, logging to stderr: {e}",
+				path.display()
+			);
+		}
+	}
+}
+
+/// Appends to a file, rotating it once it grows past `MAX_LOG_SIZE`.
+struct RotatingFile {
+	path: PathBuf,
+	file: File,
+	size: u64,
+}
+
+impl RotatingFile {
+	fn open(path: PathBuf) -> io::Result<Self> {
+		let file = OpenOptions::new().create(true).append(true).open(&path)?;
+		let size = file.metadata()?.len();
+		Ok(Self { path, file, size })
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		let mut rotated = self.path.clone().into_os_string();
+		rotated.push(".1");
+		std::fs::rename(&self.path, rotated)?;
+		*self = Self::open(self.path.clone())?;
+		Ok(())
+	}
+}
+
+impl Write for RotatingFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.size > 0 && self.size + buf.len() as u64 > MAX_LOG_SIZE {
+			self.rotate()?;
+		}
+		let written = self.file.write(buf)?;
+		self.size += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()
+	}
+}
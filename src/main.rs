@@ -1,17 +1,44 @@
-use clap::Parser;
+use std::{io::IsTerminal, net::SocketAddr, path::PathBuf};
+
+use anyhow::Context;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
-use tokio::{io::AsyncWriteExt, net::UnixStream};
+use tokio::{
+	io::{AsyncWrite, AsyncWriteExt, BufReader},
+	net::UnixStream,
+};
 
-use crate::daemon::Daemon;
+use crate::{
+	config::{Config, OnIdle, OnSleep},
+	daemon::{Daemon, SOCKET_NAME},
+	protocol::{FrameKind, read_frame, write_request},
+};
 
+mod clients;
+mod color;
+mod config;
 mod daemon;
 mod db;
+mod dbus;
+mod ical;
+mod idle;
 mod kactivities;
+mod kwin;
+mod logging;
+mod metrics;
+mod notify;
+mod protocol;
+mod screensaver;
 mod systemd;
+mod table;
+mod util;
 mod wayland;
+mod webhook;
 
-#[derive(Parser, Debug, Serialize, Deserialize)]
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Parser, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum SummaryPeriod {
 	/// Show summary for today
 	Today,
@@ -21,64 +48,722 @@ pub enum SummaryPeriod {
 	ThisMonth,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+	/// Human readable table
+	Table,
+	/// JSON, with durations in seconds
+	Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+	/// One row per session
+	Csv,
+	/// iCalendar, one event per session
+	Ics,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupBy {
+	/// One row per activity
+	Activity,
+	/// One row per app within each activity, needs window tracking
+	App,
+	/// One row per activity and day, splitting sessions at midnight
+	Day,
+	/// One row per activity and ISO week, e.g. 2024-W23
+	Week,
+	/// One row per category from the daemon's config
+	Category,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DurationFormat {
+	/// e.g. `2h 30m 0s`
+	#[default]
+	Hms,
+	/// e.g. `02:30:00`
+	Clock,
+	/// e.g. `2.5h`
+	DecimalHours,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorMode {
+	/// Color output if stdout is a terminal
+	Auto,
+	Always,
+	Never,
+}
+
+/// How the daemon should render its response, decided by the client since only it knows where
+/// the output ends up.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RenderOptions {
+	pub color: bool,
+	/// Columns available on the client's terminal, tables are shrunk to fit. None if the output
+	/// isn't a terminal, in which case nothing is truncated.
+	#[serde(default)]
+	pub width: Option<usize>,
+	#[serde(default)]
+	pub duration_format: DurationFormat,
+}
+
+/// What a client sends to the daemon, either a single `action` or a batch of `actions` run in order
+/// over the same connection.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+	#[serde(default)]
+	pub action: Option<Action>,
+	/// The response to each action ends with a [`FrameKind::End`] frame, so clients can tell
+	/// them apart.
+	#[serde(default)]
+	pub actions: Vec<Action>,
+	#[serde(default)]
+	pub render: RenderOptions,
+	/// Version of the client, empty if it predates this field
+	#[serde(default)]
+	pub version: String,
+}
+
 #[derive(Parser, Debug, Serialize, Deserialize)]
 pub enum Action {
 	/// Print summary of time spent
 	Summary {
 		#[command(subcommand)]
 		period: Option<SummaryPeriod>,
-		#[arg(long)]
+		// the field names are what goes over the socket, so only the flags are renamed
+		#[arg(long = "start", alias = "start-time")]
 		start_time: Option<String>,
-		#[arg(long)]
+		#[arg(long = "end", alias = "end-time")]
 		end_time: Option<String>,
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+		/// Only show the N activities with the most time spent
+		#[arg(long, value_name = "N")]
+		top: Option<usize>,
+		/// Combine the activities cut off by --top into an "Other" row
+		#[arg(long, requires = "top")]
+		other: bool,
+		/// Only show activities whose name or UUID contains this, ignoring case
+		#[arg(long, value_name = "SUBSTR")]
+		filter: Option<String>,
+		#[arg(long, value_enum, default_value_t = GroupBy::Activity)]
+		group_by: GroupBy,
+		/// IANA time zone used for dates and period boundaries, e.g. Europe/Berlin [default: local]
+		#[arg(long)]
+		timezone: Option<String>,
+		/// Print only the data rows, tab separated, without the header, separators or total
+		#[arg(long, alias = "quiet")]
+		no_header: bool,
+		/// Compare with the previous period of the same kind, e.g. last week for this-week, or the
+		/// same length of time before --start
+		#[arg(long, conflicts_with_all = ["top", "no_header"])]
+		compare: bool,
+		/// Also show the number of sessions, average session and longest session per activity
+		#[arg(long, conflicts_with_all = ["top", "no_header", "compare"])]
+		stats: bool,
+		/// Leave out the idle time at the end of sessions, before the idle timeout ran out. Only
+		/// counts idle periods recorded since this option was added
+		#[arg(long, conflicts_with = "stats")]
+		exclude_idle: bool,
+		/// Count sessions up to now rounded down to a multiple of this many seconds instead of the
+		/// exact time, so repeated summaries within e.g. the same minute with 60 are identical.
+		/// Only applies without --end
+		#[arg(long, value_name = "SECONDS")]
+		round_now: Option<u32>,
+		/// Also print how much of the window was tracked at all, counting only activities matching
+		/// --filter. An unbounded window starts with the first recorded session
+		#[arg(long, conflicts_with_all = ["no_header", "compare", "stats"])]
+		coverage: bool,
 	},
 	/// Print current session
-	Current,
+	///
+	/// `--format json` prints the activity's uuid, name, description and icon, its `start`,
+	/// `elapsed_seconds` and whether the session is `idle`, or `null` if nothing is tracked.
+	Current {
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+	},
+	/// Print a line each time the tracked activity or idle state changes
+	Watch {
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+	},
+	/// Report whether each of the daemon's event sources is connected
+	Status {
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+	},
+	/// List all activities known to KDE
+	List,
+	/// Show a bar chart of time tracked in each hour of the day
+	Heatmap {
+		/// Only count this activity, by name or UUID
+		activity: Option<String>,
+		#[arg(long)]
+		start: Option<String>,
+		#[arg(long)]
+		end: Option<String>,
+	},
+	/// List recorded sessions, newest first
+	Events {
+		/// Number of sessions to show [default: 50]
+		#[arg(long)]
+		limit: Option<i64>,
+		/// Number of newer sessions to skip
+		#[arg(long)]
+		offset: Option<i64>,
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+	},
+	/// List a day's sessions in order, along with the time nothing was tracked between them
+	Timeline {
+		/// Day to show, e.g. `yesterday` or 2024-06-05
+		#[arg(default_value = "today")]
+		date: String,
+		#[arg(long, value_enum, default_value_t = Format::Table)]
+		format: Format,
+	},
+	/// Export raw sessions as CSV or iCalendar
+	Export {
+		#[arg(long)]
+		start: Option<String>,
+		#[arg(long)]
+		end: Option<String>,
+		#[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+		format: ExportFormat,
+	},
+	/// Stop tracking until `resume` is run
+	Pause,
+	/// Resume tracking the current KDE activity, clearing any activity set by `start`
+	Resume,
+	/// Track a manually named activity instead of the current KDE activity
+	///
+	/// KDE activity changes are ignored until `start` is run again or tracking is resumed with
+	/// `resume`. Since the name isn't a KDE activity UUID, summaries show it literally.
+	Start { name: String },
+	/// Rename an activity in the database, merging it with `to` if that already exists
+	Rename { from: String, to: String },
+	/// Attach a note to a session, or remove it by passing an empty one
+	Note {
+		/// Session id shown in `events`, defaults to the session in progress
+		#[arg(long)]
+		id: Option<i64>,
+		text: String,
+	},
+	/// Move the start and/or end of a session by the id shown in `events`
+	Edit {
+		id: i64,
+		#[arg(long)]
+		start: Option<String>,
+		#[arg(long)]
+		end: Option<String>,
+	},
+	/// Delete a single session by the id shown in `events`
+	Delete { id: i64 },
+	/// Delete all sessions that ended before the given date
+	Purge { before: String },
+	/// Close sessions left open by a crash, keeping only the most recent one open
+	Repair,
+	/// Write a consistent copy of the database to a file, even while sessions are being recorded
+	Backup {
+		path: String,
+		/// Replace the file if it already exists
+		#[arg(long)]
+		force: bool,
+	},
+	/// List clients currently connected to the daemon
+	Connections,
+	/// Disconnect a client by the id shown in `connections`
+	Disconnect { id: u64 },
+	/// Print the client and daemon versions, warning if they differ
+	Version,
 }
 
 #[derive(Debug, Parser)]
-enum Cli {
+struct Cli {
+	/// Unix socket to use instead of the abstract socket, e.g. $XDG_RUNTIME_DIR/ktimetracker.sock
+	#[arg(long, global = true)]
+	socket_path: Option<PathBuf>,
+	/// Write the response to this file instead of stdout, errors still go to stderr
+	#[arg(long, short, global = true)]
+	output: Option<PathBuf>,
+	/// Read this database directly instead of asking the daemon, only for summary, events,
+	/// timeline, export and heatmap
+	#[arg(long)]
+	database_path: Option<String>,
+	/// Whether to color the output
+	#[arg(long, value_enum, default_value_t = ColorMode::Auto, global = true)]
+	color: ColorMode,
+	/// How to write durations in tables
+	#[arg(long, value_enum, default_value_t = DurationFormat::Hms, global = true)]
+	duration_format: DurationFormat,
+	// short only, since `summary` already has `--quiet`
+	/// Log more, can be repeated
+	#[arg(short = 'v', action = ArgAction::Count, global = true)]
+	verbose: u8,
+	/// Log less, can be repeated
+	#[arg(short = 'q', action = ArgAction::Count, global = true)]
+	quiet: u8,
+	#[command(subcommand)]
+	command: Command,
+}
+
+// parsed once at startup, so the size of the daemon's flags doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
+enum Command {
 	#[clap(flatten)]
 	Action(Action),
+	/// Print a shell completion script to stdout
+	Completions { shell: clap_complete::Shell },
 	/// Run daemon
+	///
+	/// Settings are read from the config file, with any flags given here taking precedence.
 	Daemon {
-		database_path: String,
-		#[arg(long, default_value_t = 30000)]
-		idle_timeout: u32,
+		/// Config file to read, defaults to $XDG_CONFIG_HOME/ktimetracker/config.toml
+		#[arg(long)]
+		config: Option<PathBuf>,
+		/// Database to track time in, `:memory:` keeps everything in memory and loses it on exit
+		/// [default: ~/.local/share/ktimetracker.db3]
+		#[arg(long)]
+		database_path: Option<String>,
+		/// Older spelling of `--database-path`, kept so existing invocations keep working
+		#[arg(hide = true, conflicts_with = "database_path")]
+		database_path_positional: Option<String>,
+		/// Encrypt the database with the key in this file, needs a build with the sqlcipher
+		/// feature. Existing unencrypted databases can't be opened with a key
+		#[arg(long, value_name = "FILE")]
+		database_key_file: Option<PathBuf>,
+		/// Milliseconds without input before the session is considered idle [default: 30000]
+		#[arg(long)]
+		idle_timeout: Option<u32>,
+		/// Only watch this Wayland seat for idle instead of all of them, the available seats are
+		/// logged at startup
+		#[arg(long, value_name = "NAME")]
+		seat: Option<String>,
+		/// Serve Prometheus metrics on this address, e.g. 127.0.0.1:9110
+		#[arg(long)]
+		metrics_addr: Option<SocketAddr>,
+		/// Show a desktop notification when the tracked activity changes
+		#[arg(long)]
+		notify: bool,
+		/// Record the focused app within each activity, needs KWin
+		#[arg(long)]
+		track_windows: bool,
+		/// Track idle time as an activity with this name instead of dropping it
+		#[arg(long, value_name = "NAME")]
+		idle_activity: Option<String>,
+		/// Track time spent asleep as an activity with this name instead of dropping it
+		#[arg(long, value_name = "NAME")]
+		sleep_activity: Option<String>,
+		/// What to do when the session goes idle [default: switch-to-idle-activity if there is one,
+		/// pause otherwise]
+		#[arg(long, value_enum)]
+		on_idle: Option<OnIdle>,
+		/// What to do when the system suspends [default: switch-to-sleep-activity if there is one,
+		/// pause otherwise]
+		#[arg(long, value_enum)]
+		on_sleep: Option<OnSleep>,
+		/// Milliseconds a client may take to send its request before it's disconnected [default: 5000]
+		#[arg(long)]
+		request_timeout: Option<u64>,
+		/// Milliseconds to wait for the database while something else has it locked [default: 5000]
+		#[arg(long)]
+		busy_timeout: Option<u64>,
+		/// Size of the database connection pool [default: 5]
+		#[arg(long)]
+		db_max_connections: Option<u32>,
+		/// Seconds between WAL checkpoints, 0 disables them [default: 86400]
+		#[arg(long, value_name = "SECONDS")]
+		maintenance_interval: Option<u64>,
+		/// Also VACUUM the database during maintenance while nothing is being tracked
+		#[arg(long)]
+		vacuum: bool,
+		/// Never record time spent in this activity, by name or UUID, can be given multiple times
+		#[arg(long = "exclude", value_name = "ACTIVITY")]
+		excluded_activities: Vec<String>,
+		/// Delete sessions shorter than this many seconds when they end [default: 0]
+		#[arg(long, value_name = "SECONDS")]
+		min_duration: Option<u32>,
+		/// Log to this file instead of stderr, keeping the previous 10 MiB in <file>.1
+		#[arg(long)]
+		log_file: Option<PathBuf>,
+		/// POST a JSON payload to this URL whenever what's tracked changes
+		#[arg(long, value_name = "URL")]
+		webhook_url: Option<String>,
 	},
 }
 
+fn expand_tilde(path: &str) -> anyhow::Result<PathBuf> {
+	if path != "~" && !path.starts_with("~/") {
+		return Ok(PathBuf::from(path));
+	}
+	let home = std::env::var_os("HOME").context("$HOME is not set, can't expand `~` in path")?;
+	Ok(PathBuf::from(home).join(path.trim_start_matches('~').trim_start_matches('/')))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-	env_logger::builder()
-		.filter_level(LevelFilter::Off)
-		.filter_module("ktimetracker", LevelFilter::Debug)
-		.parse_default_env()
-		.init();
-
 	let args = Cli::parse();
 
-	match args {
-		Cli::Daemon {
+	// debug by default, each -v/-q moves one level from there
+	let levels = [
+		LevelFilter::Off,
+		LevelFilter::Error,
+		LevelFilter::Warn,
+		LevelFilter::Info,
+		LevelFilter::Debug,
+		LevelFilter::Trace,
+	];
+	let level = (4 + i32::from(args.verbose) - i32::from(args.quiet)).clamp(0, 5);
+	let log_file = match &args.command {
+		Command::Daemon { log_file, .. } => log_file
+			.as_ref()
+			.map(|x| expand_tilde(&x.to_string_lossy()))
+			.transpose()?,
+		_ => None,
+	};
+	logging::init(levels[level as usize], log_file.as_deref());
+
+	let socket_path = args
+		.socket_path
+		.map(|x| expand_tilde(&x.to_string_lossy()))
+		.transpose()?;
+
+	match args.command {
+		Command::Daemon { .. } | Command::Completions { .. } if args.output.is_some() => {
+			anyhow::bail!("--output only applies to client actions");
+		}
+		Command::Daemon { .. } | Command::Completions { .. } if args.database_path.is_some() => {
+			anyhow::bail!(
+				"--database-path goes after `daemon` to set the database the daemon tracks in"
+			);
+		}
+		Command::Completions { shell } => {
+			clap_complete::generate(
+				shell,
+				&mut Cli::command(),
+				env!("CARGO_PKG_NAME"),
+				&mut std::io::stdout(),
+			);
+			Ok(())
+		}
+		Command::Daemon {
+			config,
 			database_path,
+			database_path_positional,
+			database_key_file,
 			idle_timeout,
+			seat,
+			metrics_addr,
+			notify,
+			track_windows,
+			idle_activity,
+			sleep_activity,
+			on_idle,
+			on_sleep,
+			request_timeout,
+			busy_timeout,
+			db_max_connections,
+			maintenance_interval,
+			vacuum,
+			excluded_activities,
+			min_duration,
+			log_file: _,
+			webhook_url,
 		} => {
-			let daemon = Daemon::new(idle_timeout);
-			daemon.run(&database_path).await?;
+			let database_path = database_path.or(database_path_positional);
+			// flags are applied again on every reload so they keep overriding the file
+			let load_config = move || -> anyhow::Result<Config> {
+				let mut config = Config::load(config.as_deref())?;
+				if let Some(database_path) = &database_path {
+					config.database_path = database_path.clone();
+				}
+				if database_key_file.is_some() {
+					config.database_key_file = database_key_file.clone();
+				}
+				config.database_key_file = config
+					.database_key_file
+					.map(|x| expand_tilde(&x.to_string_lossy()))
+					.transpose()?;
+				if let Some(idle_timeout) = idle_timeout {
+					config.idle_timeout = idle_timeout;
+				}
+				if seat.is_some() {
+					config.seat = seat.clone();
+				}
+				if metrics_addr.is_some() {
+					config.metrics_addr = metrics_addr;
+				}
+				if notify {
+					config.notify = true;
+				}
+				if track_windows {
+					config.track_windows = true;
+				}
+				if idle_activity.is_some() {
+					config.idle_activity = idle_activity.clone();
+				}
+				if sleep_activity.is_some() {
+					config.sleep_activity = sleep_activity.clone();
+				}
+				if on_idle.is_some() {
+					config.on_idle = on_idle;
+				}
+				if on_sleep.is_some() {
+					config.on_sleep = on_sleep;
+				}
+				if config.on_idle() == OnIdle::SwitchToIdleActivity
+					&& config.idle_activity.is_none()
+				{
+					anyhow::bail!("switching to the idle activity on idle needs an idle activity");
+				}
+				if config.on_sleep() == OnSleep::SwitchToSleepActivity
+					&& config.sleep_activity.is_none()
+				{
+					anyhow::bail!(
+						"switching to the sleep activity on sleep needs a sleep activity"
+					);
+				}
+				if let Some(request_timeout) = request_timeout {
+					config.request_timeout = request_timeout;
+				}
+				if let Some(busy_timeout) = busy_timeout {
+					config.busy_timeout = busy_timeout;
+				}
+				if let Some(db_max_connections) = db_max_connections {
+					config.db_max_connections = db_max_connections;
+				}
+				if let Some(maintenance_interval) = maintenance_interval {
+					config.maintenance_interval = maintenance_interval;
+				}
+				if vacuum {
+					config.vacuum = true;
+				}
+				config
+					.excluded_activities
+					.extend(excluded_activities.iter().cloned());
+				if let Some(min_duration) = min_duration {
+					config.min_duration = min_duration;
+				}
+				if webhook_url.is_some() {
+					config.webhook_url = webhook_url.clone();
+				}
+				if socket_path.is_some() {
+					config.socket_path = socket_path.clone();
+				}
+				config.socket_path = config
+					.socket_path
+					.map(|x| expand_tilde(&x.to_string_lossy()))
+					.transpose()?;
+
+				if !db::is_in_memory(&config.database_path) {
+					let is_url = config.database_path.starts_with("sqlite:");
+					let database_path = expand_tilde(&config.database_path)?;
+					if !is_url
+						&& let Some(parent) = database_path.parent()
+						&& !parent.as_os_str().is_empty()
+					{
+						std::fs::create_dir_all(parent)
+							.with_context(|| format!("failed to create {}", parent.display()))?;
+					}
+					config.database_path = database_path
+						.into_os_string()
+						.into_string()
+						.ok()
+						.context("database path is not valid UTF-8")?;
+				}
+				Ok(config)
+			};
+
+			let daemon = Daemon::new(load_config()?, Box::new(load_config));
+			daemon.run().await?;
 			Ok(())
 		}
-		Cli::Action(action) => {
-			let (mut rx, mut tx) = UnixStream::connect("\0dev.r58playz.ktimetracker")
-				.await?
-				.into_split();
-			let action_str = serde_json::to_string(&action)?;
-			tx.write_all(action_str.as_bytes()).await?;
+		Command::Action(mut action) => {
+			// the daemon may run in a different directory, so it needs an absolute path
+			if let Action::Backup { path, .. } = &mut action {
+				*path = std::path::absolute(&*path)
+					.context("failed to resolve backup path")?
+					.into_os_string()
+					.into_string()
+					.ok()
+					.context("backup path is not valid UTF-8")?;
+			}
+			let color = match args.color {
+				ColorMode::Always => true,
+				ColorMode::Never => false,
+				ColorMode::Auto => args.output.is_none() && std::io::stdout().is_terminal(),
+			};
+			let width = if args.output.is_none() && std::io::stdout().is_terminal() {
+				terminal_size::terminal_size().map(|(width, _)| width.0.into())
+			} else {
+				None
+			};
+			let render = RenderOptions {
+				color,
+				width,
+				duration_format: args.duration_format,
+			};
+			let mut out: Box<dyn AsyncWrite + Unpin> = match args.output {
+				Some(path) => Box::new(
+					tokio::fs::File::create(&path)
+						.await
+						.with_context(|| format!("failed to create {}", path.display()))?,
+				),
+				None => Box::new(tokio::io::stdout()),
+			};
+
+			if let Some(database_path) = args.database_path {
+				let database_path = if db::is_in_memory(&database_path) {
+					database_path
+				} else {
+					expand_tilde(&database_path)?
+						.into_os_string()
+						.into_string()
+						.ok()
+						.context("database path is not valid UTF-8")?
+				};
+				// categories and the key live in the daemon's config
+				let config = Config::load(None)?;
+				let key = config
+					.database_key_file
+					.map(|x| expand_tilde(&x.to_string_lossy()))
+					.transpose()?
+					.as_deref()
+					.map(db::read_key_file)
+					.transpose()?;
+				if let Err(err) = daemon::run_offline(
+					&mut out,
+					&database_path,
+					key,
+					config.categories,
+					action,
+					render,
+				)
+				.await
+				{
+					eprintln!("Error: {err:#}");
+					std::process::exit(1);
+				}
+				out.flush().await?;
+				return Ok(());
+			}
+
+			let stream = match socket_path {
+				Some(path) => UnixStream::connect(&path)
+					.await
+					.with_context(|| format!("failed to connect to {}", path.display()))?,
+				None => UnixStream::connect(SOCKET_NAME).await?,
+			};
+			let (rx, mut tx) = stream.into_split();
+			let is_version = matches!(action, Action::Version);
+			let request = Request {
+				action: Some(action),
+				actions: Vec::new(),
+				render,
+				version: VERSION.to_string(),
+			};
+			let request_str = serde_json::to_string(&request)?;
+			write_request(&mut tx, request_str.as_bytes()).await?;
+			// daemons from before length prefixed requests read until EOF
 			tx.shutdown().await?;
 
-			let mut stdout = tokio::io::stdout();
-			tokio::io::copy(&mut rx, &mut stdout).await?;
+			let mut stderr = tokio::io::stderr();
+			if is_version {
+				out.write_all(format!("client {VERSION}\n").as_bytes())
+					.await?;
+			}
+
+			// keep errors out of the output so they don't end up in a report file
+			let mut rx = BufReader::new(rx);
+			let mut failed = false;
+			while let Some((kind, payload)) = read_frame(&mut rx).await? {
+				if kind == FrameKind::End {
+					continue;
+				} else if kind == FrameKind::Error {
+					failed = true;
+					stderr.write_all(b"Error: ").await?;
+					stderr.write_all(&payload).await?;
+					stderr.write_all(b"\n").await?;
+				} else {
+					if is_version
+						&& let Some(daemon_version) = payload.strip_prefix(b"daemon ")
+						&& daemon_version.trim_ascii() != VERSION.as_bytes()
+					{
+						stderr
+							.write_all(
+								b"Warning: the daemon is running a different version, restart it\n",
+							)
+							.await?;
+					}
+					out.write_all(&payload).await?;
+					// `watch` never ends, so each line has to show up as it arrives
+					out.flush().await?;
+				}
+			}
+			out.flush().await?;
 
+			if failed {
+				std::process::exit(1);
+			}
 			Ok(())
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use clap::ValueEnum;
+
+	use super::*;
+
+	#[test]
+	fn cli_is_consistent() {
+		Cli::command().debug_assert();
+	}
+
+	#[test]
+	fn completions_generate_for_every_shell() {
+		for shell in clap_complete::Shell::value_variants() {
+			let mut script = Vec::new();
+			clap_complete::generate(*shell, &mut Cli::command(), "ktimetracker", &mut script);
+			assert!(!script.is_empty(), "no completions for {shell}");
+		}
+	}
+
+	#[test]
+	fn daemon_takes_the_database_path_either_way() {
+		for args in [
+			&["ktimetracker", "daemon", "/tmp/a.db3"][..],
+			&["ktimetracker", "daemon", "--database-path", "/tmp/a.db3"],
+		] {
+			let Command::Daemon {
+				database_path,
+				database_path_positional,
+				..
+			} = Cli::try_parse_from(args).unwrap().command
+			else {
+				panic!("not a daemon command");
+			};
+			assert_eq!(
+				database_path.or(database_path_positional).as_deref(),
+				Some("/tmp/a.db3")
+			);
+		}
+
+		assert!(
+			Cli::try_parse_from([
+				"ktimetracker",
+				"daemon",
+				"/tmp/a.db3",
+				"--database-path",
+				"/tmp/b.db3"
+			])
+			.is_err()
+		);
+	}
+}
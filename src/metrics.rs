@@ -0,0 +1,97 @@
+use std::{fmt::Write, net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+
+use crate::db::Database;
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+pub struct MetricsExporter {
+	db: Arc<Database>,
+}
+impl MetricsExporter {
+	pub fn new(db: Arc<Database>) -> Self {
+		Self { db }
+	}
+
+	pub async fn daemon(self, addr: SocketAddr) -> Result<()> {
+		let listener = TcpListener::bind(addr)
+			.await
+			.with_context(|| format!("failed to bind metrics listener to {addr}"))?;
+		info!("serving metrics on http://{addr}/metrics");
+
+		loop {
+			let (mut stream, _addr) = listener.accept().await?;
+			let db = self.db.clone();
+			tokio::spawn(async move {
+				if let Err(e) = Self::handle(&mut stream, &db).await {
+					error!("error handling metrics request: {e}");
+				}
+			});
+		}
+	}
+
+	async fn handle(stream: &mut TcpStream, db: &Database) -> Result<()> {
+		// only the request line matters, so a single read is enough
+		let mut buf = vec![0; 1024];
+		let len = stream.read(&mut buf).await?;
+		let request = String::from_utf8_lossy(&buf[..len]);
+		let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+
+		let (status, body) = match (request_line.next(), request_line.next()) {
+			(Some("GET"), Some("/metrics")) => ("200 OK", Self::render(db).await?),
+			_ => ("404 Not Found", "not found\n".to_string()),
+		};
+
+		let response = format!(
+			"HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+			body.len()
+		);
+		stream.write_all(response.as_bytes()).await?;
+		stream.shutdown().await?;
+		Ok(())
+	}
+
+	async fn render(db: &Database) -> Result<String> {
+		let mut out = String::new();
+
+		writeln!(
+			out,
+			"# HELP ktimetracker_activity_seconds_total Total time tracked per activity."
+		)?;
+		writeln!(out, "# TYPE ktimetracker_activity_seconds_total counter")?;
+		for (activity, duration) in db.get_summary(None, None).await? {
+			writeln!(
+				out,
+				"ktimetracker_activity_seconds_total{{activity=\"{}\"}} {}",
+				escape_label(&activity),
+				duration.num_seconds()
+			)?;
+		}
+
+		writeln!(
+			out,
+			"# HELP ktimetracker_current_activity The activity currently being tracked."
+		)?;
+		writeln!(out, "# TYPE ktimetracker_current_activity gauge")?;
+		if let Some(activity) = db.get_current_activity().await? {
+			writeln!(
+				out,
+				"ktimetracker_current_activity{{activity=\"{}\"}} 1",
+				escape_label(&activity)
+			)?;
+		}
+
+		Ok(out)
+	}
+}
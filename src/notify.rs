@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::Duration;
+use log::warn;
+use tokio::{sync::mpsc, time::timeout};
+use zbus::{Connection, proxy, zvariant::Value};
+
+use crate::{kactivities::KActivitiesConnection, util::format_duration};
+
+/// How long switching has to settle before a notification is shown.
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[proxy(
+	default_service = "org.freedesktop.Notifications",
+	default_path = "/org/freedesktop/Notifications",
+	interface = "org.freedesktop.Notifications"
+)]
+trait Notifications {
+	#[allow(clippy::too_many_arguments)]
+	fn notify(
+		&self,
+		app_name: &str,
+		replaces_id: u32,
+		app_icon: &str,
+		summary: &str,
+		body: &str,
+		actions: &[&str],
+		hints: HashMap<&str, &Value<'_>>,
+		expire_timeout: i32,
+	) -> zbus::Result<u32>;
+}
+
+struct ActivitySwitch {
+	activity: String,
+	previous: Option<Duration>,
+}
+
+/// Shows desktop notifications when the tracked activity changes or a daily goal is exceeded.
+pub struct Notifier {
+	tx: mpsc::UnboundedSender<ActivitySwitch>,
+	proxy: NotificationsProxy<'static>,
+}
+
+impl Notifier {
+	pub async fn new(conn: &Connection, kactivities_conn: KActivitiesConnection) -> Result<Self> {
+		let proxy = NotificationsProxy::new(conn)
+			.await
+			.context("failed to bind to notification server")?;
+
+		let (tx, rx) = mpsc::unbounded_channel();
+		tokio::spawn(Self::daemon(proxy.clone(), kactivities_conn, rx));
+
+		Ok(Self { tx, proxy })
+	}
+
+	/// `previous` is how long the activity that was switched away from lasted, if one was tracked.
+	pub fn activity_switched(&self, activity: String, previous: Option<Duration>) {
+		let _ = self.tx.send(ActivitySwitch { activity, previous });
+	}
+
+	/// Tells the user the activities matching `goal` took more than `limit` today. Unlike switches
+	/// these aren't debounced, the daemon only reports each goal once a day.
+	pub async fn goal_exceeded(&self, goal: &str, limit: Duration, spent: Duration) {
+		let result = self
+			.proxy
+			.notify(
+				"ktimetracker",
+				0,
+				"",
+				&format!("Daily goal for {goal} exceeded"),
+				&format!(
+					"Spent {} of {} today",
+					format_duration(spent),
+					format_duration(limit)
+				),
+				&[],
+				HashMap::new(),
+				-1,
+			)
+			.await;
+		if let Err(e) = result {
+			warn!("failed to send notification: {e}");
+		}
+	}
+
+	async fn daemon(
+		proxy: NotificationsProxy<'static>,
+		kactivities_conn: KActivitiesConnection,
+		mut rx: mpsc::UnboundedReceiver<ActivitySwitch>,
+	) {
+		let mut replaces_id = 0;
+
+		while let Some(switch) = rx.recv().await {
+			// keep the duration from before the burst, flipping through activities shouldn't reset it
+			let previous = switch.previous;
+			let mut activity = switch.activity;
+			loop {
+				match timeout(DEBOUNCE, rx.recv()).await {
+					Ok(Some(switch)) => activity = switch.activity,
+					Ok(None) => return,
+					Err(_) => break,
+				}
+			}
+
+			let (name, icon) = match kactivities_conn.query_activity_info(activity.clone()).await {
+				Ok(info) if !info.name.is_empty() => (info.name, info.icon),
+				Ok(_) => (activity, String::new()),
+				Err(e) => {
+					warn!("failed to get activity info for notification: {e}");
+					(activity, String::new())
+				}
+			};
+			let body = previous.map_or(String::new(), |x| {
+				format!("Previous activity lasted {}", format_duration(x))
+			});
+
+			match proxy
+				.notify(
+					"ktimetracker",
+					replaces_id,
+					&icon,
+					&format!("Now tracking {name}"),
+					&body,
+					&[],
+					HashMap::new(),
+					-1,
+				)
+				.await
+			{
+				Ok(id) => replaces_id = id,
+				Err(e) => warn!("failed to send notification: {e}"),
+			}
+		}
+	}
+}
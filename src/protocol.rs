@@ -0,0 +1,191 @@
+//! Framing for requests to the daemon and its responses, so both sides know where a request ends
+//! and clients can tell output apart from errors.
+//!
+//! A request is a big endian `u32` length followed by that many bytes of JSON.
+//!
+//! A response is a sequence of frames, each a kind byte, a big endian `u32` length and that many
+//! bytes of payload. The connection closing after a frame ends the response.
+//!
+//! A request with a batch of `actions` gets a response per action, each ending in an
+//! [`FrameKind::End`] frame without payload. Responses to single actions have no `End` frame.
+
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+	/// Part of the response, to be written out as is
+	Output = 0,
+	/// The request failed, the payload is the error message
+	Error = 1,
+	/// Ends the response to one action of a batch
+	End = 2,
+}
+
+/// Writes daemon output as frames. `write_all` mirrors the one on streams so `swrite!` works on it.
+pub struct ResponseWriter<W> {
+	inner: W,
+	framed: bool,
+}
+
+impl<W: AsyncWrite + Unpin> ResponseWriter<W> {
+	pub fn new(inner: W) -> Self {
+		Self {
+			inner,
+			framed: true,
+		}
+	}
+
+	/// Writes output as is, for rendering straight to the terminal without a daemon in between.
+	/// Only output is written, errors are left to whoever called the action.
+	pub fn unframed(inner: W) -> Self {
+		Self {
+			inner,
+			framed: false,
+		}
+	}
+
+	pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+		self.write_frame(FrameKind::Output, buf).await
+	}
+
+	pub async fn error(&mut self, message: &str) -> io::Result<()> {
+		self.write_frame(FrameKind::Error, message.as_bytes()).await
+	}
+
+	pub async fn end(&mut self) -> io::Result<()> {
+		self.write_frame(FrameKind::End, &[]).await
+	}
+
+	async fn write_frame(&mut self, kind: FrameKind, payload: &[u8]) -> io::Result<()> {
+		if !self.framed {
+			if kind == FrameKind::Output {
+				self.inner.write_all(payload).await?;
+			}
+			return Ok(());
+		}
+		let len = u32::try_from(payload.len())
+			.map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame is too large"))?;
+		let mut frame = Vec::with_capacity(5 + payload.len());
+		frame.push(kind as u8);
+		frame.extend_from_slice(&len.to_be_bytes());
+		frame.extend_from_slice(payload);
+		self.inner.write_all(&frame).await
+	}
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(
+	writer: &mut W,
+	payload: &[u8],
+) -> io::Result<()> {
+	let len = u32::try_from(payload.len())
+		.map_err(|_| io::Error::new(ErrorKind::InvalidInput, "request is too large"))?;
+	let mut frame = Vec::with_capacity(4 + payload.len());
+	frame.extend_from_slice(&len.to_be_bytes());
+	frame.extend_from_slice(payload);
+	writer.write_all(&frame).await
+}
+
+/// Reads a request of at most `max_len` bytes, failing cleanly if the client sends less than it
+/// announced. Clients from before the length prefix send bare JSON until EOF, which is told apart
+/// by its leading `{` since that would make for a length far over any limit.
+pub async fn read_request<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	max_len: u32,
+) -> io::Result<Vec<u8>> {
+	let too_large = || {
+		io::Error::new(
+			ErrorKind::InvalidData,
+			format!("request is larger than {max_len} bytes"),
+		)
+	};
+
+	let mut prefix = [0; 4];
+	reader
+		.read_exact(&mut prefix)
+		.await
+		.map_err(|e| match e.kind() {
+			ErrorKind::UnexpectedEof => {
+				io::Error::new(e.kind(), "connection closed before a request")
+			}
+			_ => e,
+		})?;
+	if prefix[0] == b'{' {
+		let mut buf = prefix.to_vec();
+		reader
+			.take(u64::from(max_len) + 1 - buf.len() as u64)
+			.read_to_end(&mut buf)
+			.await?;
+		if buf.len() > max_len as usize {
+			return Err(too_large());
+		}
+		return Ok(buf);
+	}
+
+	let len = u32::from_be_bytes(prefix);
+	if len > max_len {
+		return Err(too_large());
+	}
+	let mut buf = vec![0; len as usize];
+	let mut read = 0;
+	while read < buf.len() {
+		match reader.read(&mut buf[read..]).await? {
+			0 => {
+				return Err(io::Error::new(
+					ErrorKind::UnexpectedEof,
+					format!("request was cut off after {read} of {len} bytes"),
+				));
+			}
+			n => read += n,
+		}
+	}
+	Ok(buf)
+}
+
+/// Reads the next frame, or `None` once the daemon has closed the connection.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+	reader: &mut R,
+) -> io::Result<Option<(FrameKind, Vec<u8>)>> {
+	let kind = match reader.read_u8().await {
+		Ok(0) => FrameKind::Output,
+		Ok(1) => FrameKind::Error,
+		Ok(2) => FrameKind::End,
+		Ok(_) => {
+			return Err(io::Error::new(
+				ErrorKind::InvalidData,
+				"unexpected response from daemon, is it running a different version?",
+			));
+		}
+		Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e),
+	};
+	let len = reader.read_u32().await?;
+	let mut payload = vec![0; len as usize];
+	reader.read_exact(&mut payload).await?;
+	Ok(Some((kind, payload)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn truncated_request_is_an_error() {
+		let (mut client, mut daemon) = tokio::io::duplex(64);
+		client.write_all(&10u32.to_be_bytes()).await.unwrap();
+		client.write_all(b"{\"ac").await.unwrap();
+		drop(client);
+
+		let e = read_request(&mut daemon, 1024).await.unwrap_err();
+		assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+		assert_eq!(e.to_string(), "request was cut off after 4 of 10 bytes");
+	}
+
+	#[tokio::test]
+	async fn requests_round_trip() {
+		let (mut client, mut daemon) = tokio::io::duplex(64);
+		write_request(&mut client, b"{}").await.unwrap();
+		assert_eq!(read_request(&mut daemon, 1024).await.unwrap(), b"{}");
+	}
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::info;
+use tokio::sync::{mpsc::UnboundedSender, watch};
+use zbus::{Connection, proxy};
+
+use crate::{
+	daemon::{DaemonEvent, Source},
+	idle::{IdleOptions, IdleSource},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[proxy(
+	default_service = "org.freedesktop.ScreenSaver",
+	default_path = "/ScreenSaver",
+	interface = "org.freedesktop.ScreenSaver"
+)]
+trait ScreenSaver {
+	/// Milliseconds since the last user input.
+	fn get_session_idle_time(&self) -> zbus::Result<u32>;
+}
+
+/// Idle detection through the freedesktop screensaver interface, for sessions without a wayland
+/// compositor such as KDE on X11. There's no signal for idle time, so it has to be polled.
+pub struct ScreenSaverIdle;
+
+impl ScreenSaverIdle {
+	pub async fn daemon(
+		sender: UnboundedSender<DaemonEvent>,
+		idle_timeout: watch::Receiver<u32>,
+	) -> Result<()> {
+		let conn = Connection::session()
+			.await
+			.context("failed to connect to d-bus session bus")?;
+		let proxy = ScreenSaverProxy::new(&conn)
+			.await
+			.context("failed to bind to screensaver")?;
+		// fail early if nothing implements the interface
+		proxy
+			.get_session_idle_time()
+			.await
+			.context("failed to get session idle time")?;
+
+		info!("detecting idle through the screensaver interface");
+		let _ = sender.send(DaemonEvent::SourceStatus {
+			source: Source::ScreenSaver,
+			connected: true,
+		});
+
+		let result = Self::poll(&proxy, &sender, idle_timeout).await;
+		let _ = sender.send(DaemonEvent::SourceStatus {
+			source: Source::ScreenSaver,
+			connected: false,
+		});
+		result
+	}
+
+	async fn poll(
+		proxy: &ScreenSaverProxy<'_>,
+		sender: &UnboundedSender<DaemonEvent>,
+		idle_timeout: watch::Receiver<u32>,
+	) -> Result<()> {
+		let mut idle = false;
+		let mut interval = tokio::time::interval(POLL_INTERVAL);
+		loop {
+			interval.tick().await;
+			let idle_time = proxy
+				.get_session_idle_time()
+				.await
+				.context("failed to get session idle time")?;
+
+			// polling picks up a changed timeout on the next tick by itself
+			if (idle_time >= *idle_timeout.borrow()) != idle {
+				idle = !idle;
+				sender
+					.send(DaemonEvent::IdleStatusChanged { idle })
+					.context("failed to send message to daemon")?;
+			}
+		}
+	}
+}
+
+impl IdleSource for ScreenSaverIdle {
+	const NAME: &'static str = "screensaver";
+
+	fn run(
+		sender: UnboundedSender<DaemonEvent>,
+		idle_timeout: watch::Receiver<u32>,
+		_: IdleOptions,
+	) -> impl Future<Output = Result<()>> + Send {
+		Self::daemon(sender, idle_timeout)
+	}
+}
@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
-use futures::StreamExt;
-use tokio::sync::mpsc;
-use zbus::{Connection, proxy};
+use futures::{Stream, StreamExt};
+use log::warn;
+use tokio::{select, sync::mpsc};
+use zbus::{
+	Connection, proxy,
+	zvariant::{OwnedFd, OwnedObjectPath},
+};
 
-use crate::daemon::DaemonEvent;
+use crate::daemon::{DaemonEvent, Source};
 
 #[proxy(
 	default_service = "org.freedesktop.login1",
@@ -13,6 +17,28 @@ use crate::daemon::DaemonEvent;
 trait SystemdLogin1 {
 	#[zbus(signal)]
 	fn prepare_for_sleep(&self, sleeping: bool) -> zbus::Result<()>;
+
+	fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+	fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+}
+
+#[proxy(
+	default_service = "org.freedesktop.login1",
+	interface = "org.freedesktop.login1.Session"
+)]
+trait SystemdSession {
+	#[zbus(signal)]
+	fn lock(&self) -> zbus::Result<()>;
+	#[zbus(signal)]
+	fn unlock(&self) -> zbus::Result<()>;
+}
+
+async fn next_or_pending<S: Stream + Unpin>(stream: &mut Option<S>) -> Option<S::Item> {
+	match stream {
+		Some(stream) => stream.next().await,
+		None => std::future::pending().await,
+	}
 }
 
 pub struct SystemdConnection {
@@ -28,6 +54,27 @@ impl SystemdConnection {
 		Ok(Self { conn, daemon })
 	}
 
+	/// Takes a delay lock on sleep, so suspend waits for the current session to be saved.
+	async fn inhibit_sleep(proxy: &SystemdLogin1Proxy<'_>) -> Option<OwnedFd> {
+		match proxy
+			.inhibit(
+				"sleep",
+				"ktimetracker",
+				"Saving the current session",
+				"delay",
+			)
+			.await
+		{
+			Ok(fd) => Some(fd),
+			Err(e) => {
+				warn!(
+					"failed to take sleep inhibitor, the current session may not be saved before suspend: {e}"
+				);
+				None
+			}
+		}
+	}
+
 	pub async fn daemon(self) -> Result<()> {
 		let proxy = SystemdLogin1Proxy::new(&self.conn)
 			.await
@@ -37,18 +84,79 @@ impl SystemdConnection {
 			.receive_prepare_for_sleep()
 			.await
 			.context("failed to bind to prepare for sleep signal")?;
+		let mut inhibitor = Self::inhibit_sleep(&proxy).await;
+
+		// the daemon usually runs outside of any session, so ask for the user's graphical one
+		let session = match proxy.get_session("auto").await {
+			Ok(path) => Some(
+				SystemdSessionProxy::builder(&self.conn)
+					.path(path)?
+					.build()
+					.await
+					.context("failed to bind to systemd login1 session")?,
+			),
+			Err(e) => {
+				warn!("no login1 session found, not tracking screen locks: {e}");
+				None
+			}
+		};
+		let (mut lock, mut unlock) = match &session {
+			Some(session) => (
+				Some(
+					session
+						.receive_lock()
+						.await
+						.context("failed to bind to lock signal")?,
+				),
+				Some(
+					session
+						.receive_unlock()
+						.await
+						.context("failed to bind to unlock signal")?,
+				),
+			),
+			None => (None, None),
+		};
+
+		let _ = self.daemon.send(DaemonEvent::SourceStatus {
+			source: Source::Systemd,
+			connected: true,
+		});
+
+		loop {
+			select! {
+				signal = sleep.next() => {
+					let Some(signal) = signal else {
+						break;
+					};
+					let sleeping = signal.args().context("failed to parse message")?.sleeping;
 
-		while let Some(signal) = sleep.next().await {
-			let sleeping = signal.args().context("failed to parse message")?.sleeping;
-
-			if sleeping {
-				self.daemon
-					.send(DaemonEvent::SleepingNow)
-					.context("failed to send message to daemon")?;
-			} else {
-				self.daemon
-					.send(DaemonEvent::WakingNow)
-					.context("failed to send message to daemon")?;
+					if sleeping {
+						// the daemon drops the inhibitor once the session is saved
+						self.daemon
+							.send(DaemonEvent::SleepingNow {
+								inhibitor: inhibitor.take(),
+							})
+							.context("failed to send message to daemon")?;
+					} else {
+						if inhibitor.is_none() {
+							inhibitor = Self::inhibit_sleep(&proxy).await;
+						}
+						self.daemon
+							.send(DaemonEvent::WakingNow)
+							.context("failed to send message to daemon")?;
+					}
+				}
+				Some(_) = next_or_pending(&mut lock) => {
+					self.daemon
+						.send(DaemonEvent::ScreenLocked)
+						.context("failed to send message to daemon")?;
+				}
+				Some(_) = next_or_pending(&mut unlock) => {
+					self.daemon
+						.send(DaemonEvent::ScreenUnlocked)
+						.context("failed to send message to daemon")?;
+				}
 			}
 		}
 
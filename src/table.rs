@@ -0,0 +1,111 @@
+use crate::color;
+
+pub struct Table {
+	headers: Vec<String>,
+	rows: Vec<Vec<String>>,
+	footer: Option<Vec<String>>,
+	styles: Vec<Option<&'static str>>,
+	fit: Option<(usize, usize)>,
+}
+
+/// Cuts `text` down to `width` characters, ending it with an ellipsis if anything was cut.
+fn truncate(text: &str, width: usize) -> String {
+	if text.chars().count() <= width {
+		return text.to_string();
+	}
+	let mut out: String = text.chars().take(width.saturating_sub(1)).collect();
+	out.push('…');
+	out
+}
+
+impl Table {
+	pub fn new(headers: &[&str]) -> Self {
+		Self {
+			headers: headers.iter().map(|x| x.to_string()).collect(),
+			rows: Vec::new(),
+			footer: None,
+			styles: vec![None; headers.len()],
+			fit: None,
+		}
+	}
+
+	pub fn push(&mut self, row: Vec<String>) {
+		self.rows.push(row);
+	}
+
+	/// Colors the cells of `column` in `style`, except for the header. Styles are applied after
+	/// padding so they don't throw off the alignment.
+	pub fn style(&mut self, column: usize, style: &'static str) {
+		self.styles[column] = Some(style);
+	}
+
+	/// Keeps the rendered table within `width` characters by truncating cells of `column`, but
+	/// never narrower than its header. Other columns are left alone, so the table can still end up
+	/// wider if they don't fit by themselves.
+	pub fn fit(&mut self, column: usize, width: usize) {
+		self.fit = Some((column, width));
+	}
+
+	/// Sets a row that's rendered below the other rows, separated from them.
+	pub fn set_footer(&mut self, row: Vec<String>) {
+		self.footer = Some(row);
+	}
+
+	pub fn render(&self) -> String {
+		let mut widths: Vec<usize> = self.headers.iter().map(|x| x.chars().count()).collect();
+		for row in self.rows.iter().chain(&self.footer) {
+			for (width, cell) in widths.iter_mut().zip(row) {
+				*width = (*width).max(cell.chars().count());
+			}
+		}
+
+		if let Some((column, max_width)) = self.fit {
+			let total = widths.iter().sum::<usize>() + 3 * (widths.len() - 1);
+			let min = self.headers[column].chars().count();
+			widths[column] = widths[column]
+				.saturating_sub(total.saturating_sub(max_width))
+				.max(min);
+		}
+
+		let separator = widths
+			.iter()
+			.map(|width| "-".repeat(*width))
+			.collect::<Vec<_>>()
+			.join("-+-");
+		let format_row = |row: &[String], styled: bool| {
+			row.iter()
+				.zip(&widths)
+				.zip(&self.styles)
+				.map(|((cell, width), style)| {
+					let cell = format!("{:<width$}", truncate(cell, *width));
+					match style {
+						Some(style) if styled => color::paint(&cell, style, true),
+						_ => cell,
+					}
+				})
+				.collect::<Vec<_>>()
+				.join(" | ")
+		};
+
+		let mut out = String::new();
+		out.push_str(&separator);
+		out.push('\n');
+		out.push_str(&format_row(&self.headers, false));
+		out.push('\n');
+		out.push_str(&separator);
+		out.push('\n');
+		for row in &self.rows {
+			out.push_str(&format_row(row, true));
+			out.push('\n');
+		}
+		out.push_str(&separator);
+		out.push('\n');
+		if let Some(footer) = &self.footer {
+			out.push_str(&format_row(footer, true));
+			out.push('\n');
+			out.push_str(&separator);
+			out.push('\n');
+		}
+		out
+	}
+}
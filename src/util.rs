@@ -0,0 +1,148 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone};
+
+use crate::DurationFormat;
+
+/// Formats a duration as e.g. `1h 0m 5s`. Leading zero units are left out, but once a unit is
+/// shown every smaller one is too, so `1h 0m 5s` can't be misread as `1h 5m`. Sub-second
+/// remainders are dropped and durations of a second or more below zero are prefixed with `-`.
+pub fn format_duration(duration: Duration) -> String {
+	if duration.num_seconds() < 0 {
+		return format!("-{}", format_duration(duration.abs()));
+	}
+
+	let hours = duration.num_hours();
+	let minutes = duration.num_minutes() % 60;
+	let seconds = duration.num_seconds() % 60;
+	if hours > 0 {
+		format!("{hours}h {minutes}m {seconds}s")
+	} else if minutes > 0 {
+		format!("{minutes}m {seconds}s")
+	} else {
+		format!("{seconds}s")
+	}
+}
+
+/// Formats a duration in the style picked by the client, see [`format_duration`] for `Hms`.
+/// Negative durations are prefixed with `-` in every style.
+pub fn format_duration_as(duration: Duration, format: DurationFormat) -> String {
+	if duration.num_seconds() < 0 {
+		return format!("-{}", format_duration_as(duration.abs(), format));
+	}
+
+	match format {
+		DurationFormat::Hms => format_duration(duration),
+		DurationFormat::Clock => format!(
+			"{:02}:{:02}:{:02}",
+			duration.num_hours(),
+			duration.num_minutes() % 60,
+			duration.num_seconds() % 60
+		),
+		DurationFormat::DecimalHours => {
+			let hours = format!("{:.2}", duration.num_seconds() as f64 / 3600.0);
+			format!("{}h", hours.trim_end_matches('0').trim_end_matches('.'))
+		}
+	}
+}
+
+/// First instant of `date` in `tz`. Some zones skip midnight when DST starts, in which case the
+/// day starts an hour later.
+pub fn start_of_day<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> DateTime<Tz> {
+	let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+	tz.from_local_datetime(&midnight)
+		.earliest()
+		.or_else(|| {
+			tz.from_local_datetime(&(midnight + Duration::hours(1)))
+				.earliest()
+		})
+		.unwrap()
+}
+
+/// Matches `text` against a glob where `*` matches any run of characters, including none, and `?`
+/// matches exactly one. Everything else matches itself, ignoring case.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+	let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+	let text: Vec<char> = text.to_lowercase().chars().collect();
+
+	let (mut p, mut t) = (0, 0);
+	// position of the last `*` and the text position it's currently matched up to
+	let mut star = None;
+	while t < text.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+			p += 1;
+			t += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			star = Some((p, t));
+			p += 1;
+		} else if let Some((star_p, star_t)) = star {
+			// let the last `*` swallow one more character and retry
+			p = star_p + 1;
+			t = star_t + 1;
+			star = Some((star_p, star_t + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|x| *x == '*')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn durations_show_every_unit_after_the_first() {
+		assert_eq!(format_duration(Duration::hours(1)), "1h 0m 0s");
+		assert_eq!(
+			format_duration(Duration::hours(1) + Duration::seconds(5)),
+			"1h 0m 5s"
+		);
+		assert_eq!(format_duration(Duration::minutes(2)), "2m 0s");
+		assert_eq!(format_duration(Duration::milliseconds(999)), "0s");
+		assert_eq!(format_duration(Duration::milliseconds(1500)), "1s");
+		assert_eq!(format_duration(-Duration::minutes(90)), "-1h 30m 0s");
+		assert_eq!(format_duration(-Duration::milliseconds(500)), "0s");
+	}
+
+	#[test]
+	fn durations_format_in_every_style() {
+		let duration = Duration::hours(2) + Duration::minutes(30);
+		assert_eq!(
+			format_duration_as(duration, DurationFormat::Hms),
+			"2h 30m 0s"
+		);
+		assert_eq!(
+			format_duration_as(duration, DurationFormat::Clock),
+			"02:30:00"
+		);
+		assert_eq!(
+			format_duration_as(duration, DurationFormat::DecimalHours),
+			"2.5h"
+		);
+
+		assert_eq!(
+			format_duration_as(Duration::zero(), DurationFormat::Hms),
+			"0s"
+		);
+		assert_eq!(
+			format_duration_as(Duration::zero(), DurationFormat::Clock),
+			"00:00:00"
+		);
+		assert_eq!(
+			format_duration_as(Duration::zero(), DurationFormat::DecimalHours),
+			"0h"
+		);
+
+		assert_eq!(
+			format_duration_as(-duration, DurationFormat::Hms),
+			"-2h 30m 0s"
+		);
+		assert_eq!(
+			format_duration_as(-duration, DurationFormat::Clock),
+			"-02:30:00"
+		);
+		assert_eq!(
+			format_duration_as(-duration, DurationFormat::DecimalHours),
+			"-2.5h"
+		);
+	}
+}
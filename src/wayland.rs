@@ -1,25 +1,108 @@
-use std::ffi::CString;
-use tokio::sync::mpsc::UnboundedSender;
+use std::{ffi::CString, time::Duration};
+use tokio::sync::{mpsc::UnboundedSender, watch};
 
 use anyhow::{Context, Result};
-use log::info;
+use log::{info, warn};
 use wayrs_client::{Connection, protocol::WlSeat};
-use wayrs_protocols::ext_idle_notify_v1::{ExtIdleNotifierV1, ext_idle_notification_v1::Event};
+use wayrs_protocols::ext_idle_notify_v1::{
+	ExtIdleNotificationV1, ExtIdleNotifierV1, ext_idle_notification_v1::Event,
+};
 use wayrs_utils::seats::{SeatHandler, Seats};
 
-use crate::daemon::DaemonEvent;
+use crate::{
+	daemon::{DaemonEvent, Source},
+	idle::{IdleOptions, IdleSource},
+};
+
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
 
 pub struct WaylandConnection {
 	seats: Seats,
+	/// seats to watch, only the configured one once it's been picked out
 	seat_names: Vec<(CString, WlSeat)>,
+	/// name of the seat to watch, all of them if `None`
+	seat: Option<String>,
+	/// idle state of each seat in `seat_names`
+	idle_seats: Vec<bool>,
+	/// whether the daemon was last told we're idle, which is only the case once all seats are
+	idle: bool,
+	/// the bound notifier and its version, `None` until it's bound while connecting
+	idle_notifier: Option<(ExtIdleNotifierV1, u32)>,
+	/// one notification per seat in `seat_names`, recreated when the idle timeout changes
+	notifications: Vec<ExtIdleNotificationV1>,
+	/// timeout the notifications were last armed with, for seats that show up later
+	idle_timeout: u32,
 	sender: UnboundedSender<DaemonEvent>,
 }
 impl WaylandConnection {
-	pub async fn daemon(sender: UnboundedSender<DaemonEvent>, idle_timeout: u32) -> Result<()> {
+	/// Runs idle detection, reconnecting with exponential backoff if the compositor goes away.
+	/// Only gives up after `MAX_RECONNECT_ATTEMPTS` consecutive failures to connect, except for the
+	/// very first connection, which fails straight away so another backend can take over.
+	///
+	/// This uses wayrs-client's tokio integration (`async_recv_events` and friends) rather than a
+	/// blocking loop, so it's safe to run with `tokio::spawn` and never parks a runtime worker.
+	///
+	/// The idle timeout is re-armed whenever `idle_timeout` changes.
+	pub async fn daemon(
+		sender: UnboundedSender<DaemonEvent>,
+		mut idle_timeout: watch::Receiver<u32>,
+		seat: Option<String>,
+	) -> Result<()> {
+		let mut failures = 0;
+		let mut connected = false;
+		loop {
+			let timeout = *idle_timeout.borrow_and_update();
+			let result = match Self::connect(sender.clone(), timeout, seat.clone()).await {
+				Ok((mut conn, mut this)) => {
+					failures = 0;
+					connected = true;
+					let _ = sender.send(DaemonEvent::SourceStatus {
+						source: Source::Wayland,
+						connected: true,
+					});
+					let result = this.dispatch(&mut conn, &mut idle_timeout).await;
+					let _ = sender.send(DaemonEvent::SourceStatus {
+						source: Source::Wayland,
+						connected: false,
+					});
+					// a fresh connection starts out active, so don't leave the daemon thinking we're idle
+					if this.idle {
+						let _ = sender.send(DaemonEvent::IdleStatusChanged { idle: false });
+					}
+					result
+				}
+				Err(e) if !connected => return Err(e),
+				Err(e) => Err(e),
+			};
+			let e = result
+				.err()
+				.unwrap_or_else(|| anyhow::anyhow!("wayland connection closed"));
+
+			failures += 1;
+			if failures > MAX_RECONNECT_ATTEMPTS {
+				return Err(e.context("giving up on wayland connection"));
+			}
+			let backoff = Duration::from_secs(1 << (failures - 1).min(6));
+			warn!("wayland connection failed, reconnecting in {backoff:?}: {e:#}");
+			tokio::time::sleep(backoff).await;
+		}
+	}
+
+	async fn connect(
+		sender: UnboundedSender<DaemonEvent>,
+		idle_timeout: u32,
+		seat: Option<String>,
+	) -> Result<(Connection<Self>, Self)> {
 		let mut conn = Connection::connect().context("failed to connect to wayland server")?;
 		let mut this = Self {
 			seat_names: Vec::new(),
+			seat,
 			seats: Seats::new(&mut conn),
+			idle_seats: Vec::new(),
+			idle: false,
+			idle_notifier: None,
+			notifications: Vec::new(),
+			idle_timeout,
 			sender,
 		};
 
@@ -37,50 +120,200 @@ impl WaylandConnection {
 		conn.async_roundtrip().await.context("roundtrip failed")?;
 		conn.dispatch_events(&mut this);
 
-		let idle = conn
-			.bind_singleton::<ExtIdleNotifierV1>(2..=2)
-			.context("failed to bind to ext_idle_notify_v1 version 2")?;
+		// version 2 adds input idle notifications, which ignore idle inhibitors such as video players
+		// without the protocol there's no idle detection at all, so let another backend take over
+		let (idle, version) = match conn.bind_singleton::<ExtIdleNotifierV1>(2..=2) {
+			Ok(idle) => (idle, 2),
+			Err(_) => (
+				conn.bind_singleton::<ExtIdleNotifierV1>(1..=1)
+					.context("compositor lacks ext_idle_notify_v1")?,
+				1,
+			),
+		};
+
+		if this.seat_names.is_empty() {
+			anyhow::bail!("no wayland seats found");
+		}
+		let names: Vec<_> = this.seat_names.iter().map(|(name, _)| name).collect();
+		info!("found wayland seats {names:?}");
+		if let Some(seat) = &this.seat {
+			let i = match this
+				.seat_names
+				.iter()
+				.position(|(name, _)| name.as_bytes() == seat.as_bytes())
+			{
+				Some(i) => i,
+				None => {
+					warn!(
+						"wayland seat {seat:?} not found, using {:?} instead",
+						this.seat_names[0].0
+					);
+					0
+				}
+			};
+			this.seat_names = vec![this.seat_names.swap_remove(i)];
+		}
+		for (seat_name, _) in &this.seat_names {
+			info!(
+				"monitoring wayland seat {seat_name:?} for idle notifications with ext_idle_notifier_v1 version {version}"
+			);
+		}
+		this.idle_notifier = Some((idle, version));
+		this.arm(&mut conn, idle_timeout);
+
+		Ok((conn, this))
+	}
+
+	/// (Re)creates the idle notification of every seat with `idle_timeout`, starting out active.
+	fn arm(&mut self, conn: &mut Connection<Self>, idle_timeout: u32) {
+		if self.idle_notifier.is_none() {
+			return;
+		}
+
+		self.idle_timeout = idle_timeout;
+		for notification in self.notifications.drain(..) {
+			notification.destroy(conn);
+		}
+		self.idle_seats.clear();
+		for i in 0..self.seat_names.len() {
+			self.arm_seat(conn, i);
+		}
+		self.update_idle();
+	}
 
-		let (seat_name, seat) = this.seat_names.first().context("no wayland seats found")?;
+	/// Creates the idle notification of the seat at `i` in `seat_names`, starting out active.
+	fn arm_seat(&mut self, conn: &mut Connection<Self>, i: usize) {
+		let Some((idle, version)) = self.idle_notifier else {
+			return;
+		};
 
-		info!("chose wayland seat {seat_name:?} for idle notifications");
-		idle.get_idle_notification_with_cb(&mut conn, idle_timeout, *seat, |ctx| {
-			ctx.state.idle_event(ctx.event);
-		});
+		// seats can come and go, so events find their seat by proxy rather than by index
+		let seat = self.seat_names[i].1;
+		let notification = if version >= 2 {
+			idle.get_input_idle_notification_with_cb(conn, self.idle_timeout, seat, move |ctx| {
+				ctx.state.idle_event(seat, ctx.event);
+			})
+		} else {
+			idle.get_idle_notification_with_cb(conn, self.idle_timeout, seat, move |ctx| {
+				ctx.state.idle_event(seat, ctx.event);
+			})
+		};
+		self.notifications.insert(i, notification);
+		self.idle_seats.insert(i, false);
+	}
 
+	async fn dispatch(
+		&mut self,
+		conn: &mut Connection<Self>,
+		idle_timeout: &mut watch::Receiver<u32>,
+	) -> Result<()> {
 		loop {
 			conn.async_flush()
 				.await
 				.context("failed to flush wayland connection")?;
-			conn.async_recv_events()
-				.await
-				.context("failed to recv wayland events")?;
-			conn.dispatch_events(&mut this);
+			tokio::select! {
+				res = conn.async_recv_events() => {
+					res.context("failed to recv wayland events")?;
+					conn.dispatch_events(self);
+				}
+				res = idle_timeout.changed() => {
+					res.context("daemon stopped sending idle timeouts")?;
+					let timeout = *idle_timeout.borrow_and_update();
+					info!("re-arming wayland idle notifications with a timeout of {timeout}ms");
+					self.arm(conn, timeout);
+				}
+			}
 		}
 	}
 
-	fn idle_event(&mut self, event: Event) {
+	fn idle_event(&mut self, seat: WlSeat, event: Event) {
+		let Some(i) = self.seat_names.iter().position(|(_, x)| *x == seat) else {
+			return;
+		};
 		match event {
-			Event::Idled => {
-				let _ = self
-					.sender
-					.send(DaemonEvent::IdleStatusChanged { idle: true });
-			}
-			Event::Resumed => {
-				let _ = self
-					.sender
-					.send(DaemonEvent::IdleStatusChanged { idle: false });
-			}
-			_ => {}
+			Event::Idled => self.idle_seats[i] = true,
+			Event::Resumed => self.idle_seats[i] = false,
+			_ => return,
+		}
+		self.update_idle();
+	}
+
+	/// Tells the daemon if the seats went idle or came back. That's only idle once every seat is,
+	/// but resumed as soon as any seat sees input.
+	fn update_idle(&mut self) {
+		let idle = !self.idle_seats.is_empty() && self.idle_seats.iter().all(|x| *x);
+		if idle != self.idle {
+			self.idle = idle;
+			let _ = self.sender.send(DaemonEvent::IdleStatusChanged { idle });
 		}
 	}
 }
 
+impl IdleSource for WaylandConnection {
+	const NAME: &'static str = "wayland";
+
+	fn run(
+		sender: UnboundedSender<DaemonEvent>,
+		idle_timeout: watch::Receiver<u32>,
+		options: IdleOptions,
+	) -> impl Future<Output = Result<()>> + Send {
+		Self::daemon(sender, idle_timeout, options.seat)
+	}
+}
+
 impl SeatHandler for WaylandConnection {
 	fn get_seats(&mut self) -> &mut Seats {
 		&mut self.seats
 	}
-	fn seat_name(&mut self, _: &mut Connection<Self>, seat: WlSeat, name: std::ffi::CString) {
+	fn seat_name(&mut self, conn: &mut Connection<Self>, seat: WlSeat, name: std::ffi::CString) {
+		// seats are picked out once connected, until then every one is a candidate
+		if self.idle_notifier.is_none() {
+			self.seat_names.push((name, seat));
+			return;
+		}
+
+		if self.seat_names.iter().any(|(_, x)| *x == seat) {
+			return;
+		}
+		// a seat that shows up later is watched too, or only the configured one if it's back
+		if let Some(configured) = &self.seat
+			&& (configured.as_bytes() != name.as_bytes()
+				|| self.seat_names.iter().any(|(x, _)| x == &name))
+		{
+			return;
+		}
+		info!("monitoring new wayland seat {name:?} for idle notifications");
 		self.seat_names.push((name, seat));
+		self.arm_seat(conn, self.seat_names.len() - 1);
+		self.update_idle();
+	}
+
+	fn seat_removed(&mut self, conn: &mut Connection<Self>, seat: WlSeat) {
+		let Some(i) = self.seat_names.iter().position(|(_, x)| *x == seat) else {
+			return;
+		};
+		let (name, _) = self.seat_names.remove(i);
+		if i < self.notifications.len() {
+			info!("wayland seat {name:?} was removed");
+			self.notifications.remove(i).destroy(conn);
+			self.idle_seats.remove(i);
+			self.update_idle();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Compile-time check that the backend is driven by the daemon's tokio channel and can be
+	/// spawned onto the runtime like every other event source.
+	#[test]
+	fn runs_on_the_daemon_event_channel() {
+		fn spawnable<F: Future<Output = Result<()>> + Send + 'static>(_: &F) {}
+
+		let (sender, _events) = tokio::sync::mpsc::unbounded_channel::<DaemonEvent>();
+		let (_idle_timeout_tx, idle_timeout) = watch::channel(30_000);
+		spawnable(&WaylandConnection::daemon(sender, idle_timeout, None));
 	}
 }
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use log::{debug, warn};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// Attempts made to deliver each event before it's dropped.
+const ATTEMPTS: u32 = 3;
+/// How long a single attempt may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+	ActivityChanged,
+	Idle,
+	Active,
+	Sleep,
+	Wake,
+}
+
+#[derive(Serialize)]
+struct Payload {
+	event: WebhookEvent,
+	activity: Option<String>,
+	at: String,
+}
+
+/// POSTs tracking changes as JSON to a user supplied URL. Events are delivered in order from a
+/// background task, so a slow or failing endpoint never holds up the daemon.
+pub struct Webhook {
+	tx: mpsc::UnboundedSender<Payload>,
+}
+
+impl Webhook {
+	pub fn new(url: String) -> Result<Self> {
+		let client = reqwest::Client::builder()
+			.timeout(TIMEOUT)
+			.build()
+			.context("failed to create http client")?;
+
+		let (tx, rx) = mpsc::unbounded_channel();
+		tokio::spawn(Self::daemon(client, url, rx));
+
+		Ok(Self { tx })
+	}
+
+	/// `activity` is what's tracked from now on, if anything.
+	pub fn send(&self, event: WebhookEvent, activity: Option<String>) {
+		let _ = self.tx.send(Payload {
+			event,
+			activity,
+			at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+		});
+	}
+
+	async fn daemon(
+		client: reqwest::Client,
+		url: String,
+		mut rx: mpsc::UnboundedReceiver<Payload>,
+	) {
+		while let Some(payload) = rx.recv().await {
+			for attempt in 1..=ATTEMPTS {
+				match Self::post(&client, &url, &payload).await {
+					Ok(()) => break,
+					Err(e) if attempt < ATTEMPTS => {
+						let backoff = Duration::from_secs(1 << attempt);
+						debug!("webhook attempt {attempt} failed, retrying in {backoff:?}: {e:#}");
+						tokio::time::sleep(backoff).await;
+					}
+					Err(e) => warn!("failed to deliver {:?} webhook: {e:#}", payload.event),
+				}
+			}
+		}
+	}
+
+	async fn post(client: &reqwest::Client, url: &str, payload: &Payload) -> Result<()> {
+		client
+			.post(url)
+			.json(payload)
+			.send()
+			.await
+			.context("failed to send webhook")?
+			.error_for_status()
+			.context("webhook was rejected")?;
+		Ok(())
+	}
+}